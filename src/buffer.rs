@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use byteorder::{BigEndian, ByteOrder};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Which packet to throw away when a full queue gets another one
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DropPolicy {
+    Oldest, // Make room by dropping from the front of the queue
+    Newest, // Drop the packet that doesn't fit
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "oldest" => Ok(DropPolicy::Oldest),
+            "newest" => Ok(DropPolicy::Newest),
+            _ => Err(format!("Unknown drop policy: {}", s)),
+        }
+    }
+}
+
+/// Data packets for a single destination waiting for a route to it as per section 6.3 of the RFC
+#[derive(Clone, Debug)]
+pub struct PacketQueue {
+    max_bytes: usize,
+    max_packets: usize,
+    policy: DropPolicy,
+    packets: VecDeque<Vec<u8>>,
+    bytes: usize, // Total size of `packets`
+    dropped: usize,
+}
+
+impl PacketQueue {
+    /// Return a queue holding at most `max_packets` packets and `max_bytes` bytes
+    pub fn new(max_bytes: usize, max_packets: usize, policy: DropPolicy) -> Self {
+        PacketQueue {
+            max_bytes,
+            max_packets,
+            policy,
+            packets: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+    /// Queue a packet, dropping whatever the policy says to if it doesn't fit
+    pub fn push(&mut self, packet: Vec<u8>) {
+        if packet.len() > self.max_bytes || self.max_packets == 0 {
+            self.dropped += 1;
+            return;
+        }
+        while self.packets.len() >= self.max_packets || self.bytes + packet.len() > self.max_bytes {
+            match self.policy {
+                DropPolicy::Oldest => {
+                    let old = self.packets.pop_front().unwrap();
+                    self.bytes -= old.len();
+                    self.dropped += 1;
+                }
+                DropPolicy::Newest => {
+                    self.dropped += 1;
+                    return;
+                }
+            }
+        }
+        self.bytes += packet.len();
+        self.packets.push_back(packet);
+    }
+    /// Remove and return every queued packet, oldest first
+    pub fn drain(&mut self) -> Vec<Vec<u8>> {
+        self.bytes = 0;
+        self.packets.drain(..).collect()
+    }
+    /// Return how many packets were dropped so far
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+    /// Return the total size of the queued packets
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+}
+
+/// Return an ICMP Destination Unreachable (host unreachable) for an IPv4 `packet` that couldn't
+/// be delivered, sent from `from` back to the packet's source as per RFC 792.
+///
+/// Return `None` if `packet` isn't an IPv4 packet.
+pub fn destination_unreachable(from: Ipv4Addr, packet: &[u8]) -> Option<Vec<u8>> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    if header_len < 20 || packet.len() < header_len {
+        return None;
+    }
+    // The original IP header and the first 64 bits of its data
+    let quoted = &packet[..packet.len().min(header_len + 8)];
+
+    let mut icmp = vec![3, 1, 0, 0, 0, 0, 0, 0];
+    icmp.extend_from_slice(quoted);
+    let sum = checksum(&icmp);
+    BigEndian::write_u16(&mut icmp[2..4], sum);
+
+    let total_len = 20 + icmp.len();
+    let mut ip = vec![
+        0x45, 0, 0, 0, // Version, IHL, TOS, total length
+        0, 0, 0, 0, // Identification, flags, fragment offset
+        64, 1, 0, 0, // TTL, protocol (ICMP), header checksum
+    ];
+    BigEndian::write_u16(&mut ip[2..4], total_len as u16);
+    ip.extend_from_slice(&from.octets());
+    ip.extend_from_slice(&packet[12..16]); // Back to the original source
+    let sum = checksum(&ip);
+    BigEndian::write_u16(&mut ip[10..12], sum);
+
+    ip.extend(icmp);
+    Some(ip)
+}
+
+/// The internet checksum of RFC 1071
+pub fn checksum(b: &[u8]) -> u16 {
+    let mut sum: u32 = b
+        .chunks(2)
+        .map(|c| u32::from(c[0]) << 8 | u32::from(*c.get(1).unwrap_or(&0)))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test_buffer {
+    use super::*;
+
+    #[test]
+    fn test_drop_policies() {
+        let mut q = PacketQueue::new(10, 3, DropPolicy::Oldest);
+        for p in 1..5 {
+            q.push(vec![p]);
+        }
+        assert_eq!(q.drain(), vec![vec![2], vec![3], vec![4]]);
+        // Make room by bytes as well
+        q.push(vec![1; 6]);
+        q.push(vec![2; 6]);
+        assert_eq!((q.len(), q.bytes(), q.dropped()), (1, 6, 2));
+
+        let mut q = PacketQueue::new(10, 3, DropPolicy::Newest);
+        for p in 1..5 {
+            q.push(vec![p]);
+        }
+        q.push(vec![0; 11]);
+        assert_eq!(q.drain(), vec![vec![1], vec![2], vec![3]]);
+        assert_eq!(q.dropped(), 2);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn test_destination_unreachable() {
+        let mut packet = vec![
+            0x45, 0, 0, 32, 0, 1, 0, 0, 64, 17, 0, 0, 192, 168, 10, 19, 192, 168, 10, 14,
+        ];
+        packet.extend_from_slice(&[0xaa; 12]);
+        let own_ip = Ipv4Addr::new(192, 168, 10, 19);
+        let icmp = destination_unreachable(own_ip, &packet).unwrap();
+
+        assert_eq!(icmp.len(), 20 + 8 + 28);
+        assert_eq!(&icmp[12..16], &[192, 168, 10, 19]);
+        assert_eq!(&icmp[16..20], &[192, 168, 10, 19]);
+        assert_eq!(&icmp[20..22], &[3, 1]);
+        assert_eq!(&icmp[28..], &packet[..28]);
+        // Both checksums verify
+        assert_eq!(checksum(&icmp[..20]), 0);
+        assert_eq!(checksum(&icmp[20..]), 0);
+
+        assert!(destination_unreachable(own_ip, &[0x60; 40]).is_none());
+        assert!(destination_unreachable(own_ip, &packet[..12]).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+use std::net::Ipv4Addr;
+
+use super::error::BuildError;
+use super::{Extension, RREP, RREQ};
+
+/// The J, R, G and D flags of a RREQ. The U flag follows from whether a destination sequence
+/// number was given.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RreqFlags {
+    pub j: bool, // Join flag
+    pub r: bool, // Repair flag
+    pub g: bool, // Gratuitous RREP flag
+    pub d: bool, // Destination Only flag
+}
+
+/// Builds a RREQ, checking that the destination and originator were given
+#[derive(Clone, Debug, Default)]
+pub struct RreqBuilder {
+    flags: RreqFlags,
+    hop_count: u8,
+    rreq_id: u32,
+    dest: Option<Ipv4Addr>,
+    dest_seq_num: Option<u32>, // None sets the U flag
+    orig: Option<(Ipv4Addr, u32)>,
+    extensions: Vec<Extension>,
+}
+
+impl RreqBuilder {
+    pub fn new() -> Self {
+        RreqBuilder::default()
+    }
+    /// Look for `ip`, whose sequence number is unknown unless `dest_seq_num` is called too
+    pub fn dest(mut self, ip: Ipv4Addr) -> Self {
+        self.dest = Some(ip);
+        self
+    }
+    /// Ask for a route at least as fresh as `seq_num`, clearing the U flag
+    pub fn dest_seq_num(mut self, seq_num: u32) -> Self {
+        self.dest_seq_num = Some(seq_num);
+        self
+    }
+    pub fn orig(mut self, ip: Ipv4Addr, seq_num: u32) -> Self {
+        self.orig = Some((ip, seq_num));
+        self
+    }
+    pub fn rreq_id(mut self, rreq_id: u32) -> Self {
+        self.rreq_id = rreq_id;
+        self
+    }
+    pub fn hop_count(mut self, hop_count: u8) -> Self {
+        self.hop_count = hop_count;
+        self
+    }
+    pub fn flags(mut self, flags: RreqFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+    pub fn extension(mut self, extension: Extension) -> Self {
+        self.extensions.push(extension);
+        self
+    }
+    pub fn build(self) -> Result<RREQ, BuildError> {
+        let dest_ip = self.dest.ok_or(BuildError::Missing("destination"))?;
+        let (orig_ip, orig_seq_num) = self.orig.ok_or(BuildError::Missing("originator"))?;
+        Ok(RREQ {
+            j: self.flags.j,
+            r: self.flags.r,
+            g: self.flags.g,
+            d: self.flags.d,
+            u: self.dest_seq_num.is_none(),
+            hop_count: self.hop_count,
+            rreq_id: self.rreq_id,
+            dest_ip,
+            dest_seq_num: self.dest_seq_num.unwrap_or(0),
+            orig_ip,
+            orig_seq_num,
+            extensions: self.extensions,
+        })
+    }
+}
+
+/// Builds a RREP, checking that the route's ends were given and the prefix size fits
+#[derive(Clone, Debug, Default)]
+pub struct RrepBuilder {
+    r: bool,
+    a: bool,
+    prefix_size: u8,
+    hop_count: u8,
+    dest: Option<(Ipv4Addr, u32)>,
+    orig: Option<Ipv4Addr>,
+    lifetime: u32,
+    extensions: Vec<Extension>,
+}
+
+impl RrepBuilder {
+    pub fn new() -> Self {
+        RrepBuilder::default()
+    }
+    pub fn dest(mut self, ip: Ipv4Addr, seq_num: u32) -> Self {
+        self.dest = Some((ip, seq_num));
+        self
+    }
+    pub fn orig(mut self, ip: Ipv4Addr) -> Self {
+        self.orig = Some(ip);
+        self
+    }
+    pub fn hop_count(mut self, hop_count: u8) -> Self {
+        self.hop_count = hop_count;
+        self
+    }
+    /// How long the route stays valid, in milliseconds
+    pub fn lifetime(mut self, lifetime: u32) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+    /// Reply for the whole subnet as per section 6.6 of the RFC, which must be at most 31 bits
+    pub fn prefix_size(mut self, prefix_size: u8) -> Self {
+        self.prefix_size = prefix_size;
+        self
+    }
+    pub fn repair(mut self, r: bool) -> Self {
+        self.r = r;
+        self
+    }
+    pub fn ack_required(mut self, a: bool) -> Self {
+        self.a = a;
+        self
+    }
+    pub fn extension(mut self, extension: Extension) -> Self {
+        self.extensions.push(extension);
+        self
+    }
+    pub fn build(self) -> Result<RREP, BuildError> {
+        if self.prefix_size > 31 {
+            return Err(BuildError::PrefixTooLong(self.prefix_size));
+        }
+        let (dest_ip, dest_seq_num) = self.dest.ok_or(BuildError::Missing("destination"))?;
+        let orig_ip = self.orig.ok_or(BuildError::Missing("originator"))?;
+        Ok(RREP {
+            r: self.r,
+            a: self.a,
+            prefix_size: self.prefix_size,
+            hop_count: self.hop_count,
+            dest_ip,
+            dest_seq_num,
+            orig_ip,
+            lifetime: self.lifetime,
+            extensions: self.extensions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_builder {
+    use super::*;
+
+    #[test]
+    fn test_rreq_builder() {
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let orig = Ipv4Addr::new(192, 168, 10, 19);
+        let rreq = RREQ::builder()
+            .dest(dest)
+            .orig(orig, 63)
+            .rreq_id(14425)
+            .flags(RreqFlags {
+                g: true,
+                ..RreqFlags::default()
+            })
+            .build()
+            .unwrap();
+        assert!(rreq.g && rreq.u && !rreq.j);
+        assert_eq!((rreq.dest_ip, rreq.dest_seq_num), (dest, 0));
+        assert_eq!((rreq.orig_ip, rreq.orig_seq_num), (orig, 63));
+
+        let rreq = RREQ::builder()
+            .dest(dest)
+            .dest_seq_num(12)
+            .orig(orig, 63)
+            .build()
+            .unwrap();
+        assert!(!rreq.u);
+        assert_eq!(rreq.dest_seq_num, 12);
+
+        assert_eq!(
+            RREQ::builder().orig(orig, 63).build(),
+            Err(BuildError::Missing("destination"))
+        );
+        assert_eq!(
+            RREQ::builder().dest(dest).build(),
+            Err(BuildError::Missing("originator"))
+        );
+    }
+
+    #[test]
+    fn test_rrep_builder() {
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let orig = Ipv4Addr::new(192, 168, 10, 19);
+        let rrep = RREP::builder()
+            .dest(dest, 12)
+            .orig(orig)
+            .hop_count(98)
+            .prefix_size(31)
+            .lifetime(32603)
+            .repair(true)
+            .build()
+            .unwrap();
+        let bytes: &[u8] = &[
+            2, 128, 31, 98, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 19, 0, 0, 127, 91,
+        ];
+        assert_eq!(rrep.bit_message(), bytes);
+
+        assert_eq!(
+            RREP::builder()
+                .dest(dest, 12)
+                .orig(orig)
+                .prefix_size(32)
+                .build(),
+            Err(BuildError::PrefixTooLong(32))
+        );
+        assert_eq!(
+            RREP::builder().dest(dest, 12).build(),
+            Err(BuildError::Missing("originator"))
+        );
+    }
+
+    #[test]
+    fn test_rrep_helpers() {
+        let own_ip = Ipv4Addr::new(192, 168, 10, 14);
+        let hello = RREP::hello(own_ip, 12, 2000);
+        assert!(hello.is_hello());
+        assert_eq!(hello.lifetime, 2000);
+
+        let rreq = RREQ::builder()
+            .dest(own_ip)
+            .orig(Ipv4Addr::new(192, 168, 10, 19), 63)
+            .build()
+            .unwrap();
+        let rrep = RREP::reply_to(&rreq, 13, 0, 3000);
+        assert_eq!((rrep.dest_ip, rrep.orig_ip), (rreq.dest_ip, rreq.orig_ip));
+        assert_eq!((rrep.dest_seq_num, rrep.lifetime), (13, 3000));
+        assert!(!rrep.is_hello());
+    }
+}
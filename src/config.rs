@@ -3,7 +3,7 @@ extern crate clap;
 extern crate yaml_rust;
 
 use self::chrono::Duration;
-use self::clap::{App, Arg, ArgMatches};
+use self::clap::{App, Arg, ArgMatches, SubCommand};
 use self::yaml_rust::YamlLoader;
 
 use std::fs::File;
@@ -12,30 +12,60 @@ use std::io::BufReader;
 use std::net::Ipv4Addr;
 use std::str::FromStr;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::buffer::DropPolicy;
+use super::ratelimit::RateLimitPolicy;
+
 /// The object that holds both user-set variables and aodv constants
 #[allow(non_snake_case)]
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct Config {
     pub current_ip: Ipv4Addr,
     pub interface: String,
     pub broadcast_address: Ipv4Addr,
     pub port: u16,
+    pub rate_limit_policy: RateLimitPolicy,
+    pub state_file: Option<String>, // Where to keep state across restarts, if anywhere
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
+    pub state_save_interval: Duration, // How often to save it while running
+    pub buffer_max_bytes: usize,    // Most data held per destination awaiting a route
+    pub buffer_max_packets: usize,  // Most packets held per destination awaiting a route
+    pub buffer_drop_policy: DropPolicy, // Which packet to drop when that is exceeded
+    pub quarantine_threshold: usize, // Malformed datagrams before a source is ignored, 0 for never
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
+    pub quarantine_time: Duration, // How long to count them for and to ignore the source for
+    pub mtu: usize,                 // Largest IP packet the interface sends
+    pub aggregate_messages: bool,   // Send messages for the same next hop in one datagram
+    pub capture_file: Option<String>, // pcap file to record every message sent and received in
 
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub ACTIVE_ROUTE_TIMEOUT: Duration,
     pub ALLOWED_HELLO_LOSS: u32,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub BLACKLIST_TIMEOUT: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub DELETE_PERIOD: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub HELLO_INTERVAL: Duration,
     pub LOCAL_ADD_TTL: usize,
     pub MAX_REPAIR_TTL: f64,
     pub MIN_REPAIR_TTL: usize,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub MY_ROUTE_TIMEOUT: Duration,
     pub NET_DIAMETER: usize,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub NET_TRAVERSAL_TIME: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub NEXT_HOP_WAIT: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub NODE_TRAVERSAL_TIME: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub PATH_DISCOVERY_TIME: Duration,
     pub RERR_RATELIMIT: usize,
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::millis"))]
     pub RING_TRAVERSAL_TIME: Duration,
     pub RREQ_RETRIES: usize,
     pub RREQ_RATELIMIT: usize,
@@ -74,81 +104,129 @@ impl Config {
         };
 
         // Read string file into Yaml file
-        let yaml_file;
-        match YamlLoader::load_from_str(&contents) {
-            Ok(y) => yaml_file = y,
+        let yaml_file = match YamlLoader::load_from_str(&contents) {
+            Ok(y) => y,
             Err(_) => {
                 //TODO: log.Println("Unable to parse yaml, using default")
                 return;
             }
-        }
+        };
         // First doc (there is multi-document support)
         let doc = &yaml_file[0];
 
         // Replace appropriate arguments
-        doc["Interface"]
-            .as_str()
-            .map(|x| self.interface = String::from(x));
-        doc["BroadcastAddress"].as_str().map(|x| {
-            if Ipv4Addr::from_str(x).is_ok() {
-                self.broadcast_address = Ipv4Addr::from_str(x).unwrap();
+        if let Some(x) = doc["Interface"].as_str() {
+            self.interface = String::from(x);
+        }
+        if let Some(x) = doc["BroadcastAddress"].as_str() {
+            if let Ok(ip) = Ipv4Addr::from_str(x) {
+                self.broadcast_address = ip;
+            }
+        }
+        if let Some(x) = doc["Port"].as_i64() {
+            self.port = x as u16;
+        }
+        if let Some(x) = doc["RateLimitPolicy"].as_str() {
+            if let Ok(policy) = RateLimitPolicy::from_str(x) {
+                self.rate_limit_policy = policy;
             }
-        });
-        doc["Port"].as_i64().map(|x| self.port = x as u16);
-        doc["ACTIVE_ROUTE_TIMEOUT"]
-            .as_i64()
-            .map(|x| self.ACTIVE_ROUTE_TIMEOUT = Duration::milliseconds(x));
-        doc["ALLOWED_HELLO_LOSS"]
-            .as_i64()
-            .map(|x| self.ALLOWED_HELLO_LOSS = x as u32);
-        doc["HELLO_INTERVAL"]
-            .as_i64()
-            .map(|x| self.HELLO_INTERVAL = Duration::milliseconds(x));
-        doc["LOCAL_ADD_TTL"]
-            .as_i64()
-            .map(|x| self.LOCAL_ADD_TTL = x as usize);
-        doc["NET_DIAMETER"]
-            .as_i64()
-            .map(|x| self.NET_DIAMETER = x as usize);
-        doc["NODE_TRAVERSAL_TIME"]
-            .as_i64()
-            .map(|x| self.NODE_TRAVERSAL_TIME = Duration::milliseconds(x));
-        doc["RERR_RATELIMIT"]
-            .as_i64()
-            .map(|x| self.RERR_RATELIMIT = x as usize);
-        doc["RREQ_RETRIES"]
-            .as_i64()
-            .map(|x| self.RREQ_RETRIES = x as usize);
-        doc["RREQ_RATELIMIT"]
-            .as_i64()
-            .map(|x| self.RREQ_RATELIMIT = x as usize);
-        doc["TIMEOUT_BUFFER"]
-            .as_i64()
-            .map(|x| self.TIMEOUT_BUFFER = x as usize);
-        doc["TTL_START"]
-            .as_i64()
-            .map(|x| self.TTL_START = x as usize);
-        doc["TTL_INCREMENT"]
-            .as_i64()
-            .map(|x| self.TTL_INCREMENT = x as usize);
-        doc["TTL_THRESHOLD"]
-            .as_i64()
-            .map(|x| self.TTL_THRESHOLD = x as usize);
+        }
+        if let Some(x) = doc["StateFile"].as_str() {
+            self.state_file = Some(String::from(x));
+        }
+        if let Some(x) = doc["StateSaveInterval"].as_i64() {
+            if x < 0 {
+                eprintln!("StateSaveInterval must not be negative, ignoring {}", x);
+            } else {
+                self.state_save_interval = Duration::milliseconds(x);
+            }
+        }
+        if let Some(x) = doc["BufferMaxBytes"].as_i64() {
+            self.buffer_max_bytes = x as usize;
+        }
+        if let Some(x) = doc["BufferMaxPackets"].as_i64() {
+            self.buffer_max_packets = x as usize;
+        }
+        if let Some(x) = doc["BufferDropPolicy"].as_str() {
+            if let Ok(policy) = DropPolicy::from_str(x) {
+                self.buffer_drop_policy = policy;
+            }
+        }
+        if let Some(x) = doc["QuarantineThreshold"].as_i64() {
+            self.quarantine_threshold = x as usize;
+        }
+        if let Some(x) = doc["QuarantineTime"].as_i64() {
+            self.quarantine_time = Duration::milliseconds(x);
+        }
+        if let Some(x) = doc["MTU"].as_i64() {
+            self.mtu = x as usize;
+        }
+        if let Some(x) = doc["AggregateMessages"].as_bool() {
+            self.aggregate_messages = x;
+        }
+        if let Some(x) = doc["CaptureFile"].as_str() {
+            self.capture_file = Some(String::from(x));
+        }
+        if let Some(x) = doc["ACTIVE_ROUTE_TIMEOUT"].as_i64() {
+            self.ACTIVE_ROUTE_TIMEOUT = Duration::milliseconds(x);
+        }
+        if let Some(x) = doc["ALLOWED_HELLO_LOSS"].as_i64() {
+            self.ALLOWED_HELLO_LOSS = x as u32;
+        }
+        if let Some(x) = doc["HELLO_INTERVAL"].as_i64() {
+            self.HELLO_INTERVAL = Duration::milliseconds(x);
+        }
+        if let Some(x) = doc["LOCAL_ADD_TTL"].as_i64() {
+            self.LOCAL_ADD_TTL = x as usize;
+        }
+        if let Some(x) = doc["NET_DIAMETER"].as_i64() {
+            self.NET_DIAMETER = x as usize;
+        }
+        if let Some(x) = doc["NODE_TRAVERSAL_TIME"].as_i64() {
+            self.NODE_TRAVERSAL_TIME = Duration::milliseconds(x);
+        }
+        if let Some(x) = doc["RERR_RATELIMIT"].as_i64() {
+            self.RERR_RATELIMIT = x as usize;
+        }
+        if let Some(x) = doc["RREQ_RETRIES"].as_i64() {
+            self.RREQ_RETRIES = x as usize;
+        }
+        if let Some(x) = doc["RREQ_RATELIMIT"].as_i64() {
+            self.RREQ_RATELIMIT = x as usize;
+        }
+        if let Some(x) = doc["TIMEOUT_BUFFER"].as_i64() {
+            self.TIMEOUT_BUFFER = x as usize;
+        }
+        if let Some(x) = doc["TTL_START"].as_i64() {
+            self.TTL_START = x as usize;
+        }
+        if let Some(x) = doc["TTL_INCREMENT"].as_i64() {
+            self.TTL_INCREMENT = x as usize;
+        }
+        if let Some(x) = doc["TTL_THRESHOLD"].as_i64() {
+            self.TTL_THRESHOLD = x as usize;
+        }
 
         self.compute_values();
     }
     /// Change values passed in via command line flags
     fn read_args(&mut self, args: &ArgMatches) {
-        args.value_of("current_ip").map(|x| {
+        if let Some(x) = args.value_of("current_ip") {
             if let Ok(ip) = Ipv4Addr::from_str(x) {
                 self.current_ip = ip
             }
-        });
-        args.value_of("port").map(|x| {
+        }
+        if let Some(x) = args.value_of("port") {
             if let Ok(port) = x.parse::<u16>() {
                 self.port = port
             }
-        });
+        }
+        if let Some(x) = args.value_of("state_file") {
+            self.state_file = Some(String::from(x));
+        }
+        if let Some(x) = args.value_of("capture_file") {
+            self.capture_file = Some(String::from(x));
+        }
     }
     /// Compute config values dependent on user set ones
     fn compute_values(&mut self) {
@@ -169,6 +247,10 @@ impl Config {
         self.RING_TRAVERSAL_TIME =
             self.NODE_TRAVERSAL_TIME * (2 * (self.TTL_VALUE + self.TIMEOUT_BUFFER)) as i32;
     }
+    /// RING_TRAVERSAL_TIME for a RREQ sent with the given IP TTL
+    pub fn ring_traversal_time(&self, ttl: usize) -> Duration {
+        self.NODE_TRAVERSAL_TIME * (2 * (ttl + self.TIMEOUT_BUFFER)) as i32
+    }
 }
 
 impl Default for Config {
@@ -179,6 +261,17 @@ impl Default for Config {
             interface: "wlano".parse().unwrap(),
             broadcast_address: Ipv4Addr::new(255, 255, 255, 255),
             port: 1200,
+            rate_limit_policy: RateLimitPolicy::Queue,
+            state_file: None,
+            state_save_interval: Duration::milliseconds(1000),
+            buffer_max_bytes: 65_536,
+            buffer_max_packets: 64,
+            buffer_drop_policy: DropPolicy::Oldest,
+            quarantine_threshold: 0,
+            quarantine_time: Duration::milliseconds(30_000),
+            mtu: 1500,
+            aggregate_messages: false,
+            capture_file: None,
 
             ACTIVE_ROUTE_TIMEOUT: Duration::milliseconds(3000),
             ALLOWED_HELLO_LOSS: 2,
@@ -241,6 +334,46 @@ pub fn get_args() -> ArgMatches<'static> {
                 .help("Alternate config file")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("state_file")
+                .long("state")
+                .value_name("STATE FILE")
+                .help("Save and restore the node's state across restarts")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("capture_file")
+                .long("capture")
+                .value_name("PCAP FILE")
+                .help("Record every message sent and received in a pcap file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("PCAP FILE")
+                .help("Feed the messages in a pcap file through a node with their original timing")
+                .takes_value(true)
+                .conflicts_with("start_aodv"),
+        )
+        .subcommand(
+            SubCommand::with_name("decode")
+                .about("Dissect the bytes of AODV messages field by field")
+                .arg(
+                    Arg::with_name("hex")
+                        .value_name("HEX")
+                        .help("The message bytes in hex, read from stdin if not given"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("Read the raw message bytes from a binary file instead")
+                        .takes_value(true)
+                        .conflicts_with("hex"),
+                ),
+        )
         .get_matches();
 
     // Validate submitted Ipv4Addr
@@ -257,6 +390,17 @@ fn test_parse_config() {
     let config = r#"Interface: "wlan1"
 BroadcastAddress: "192.168.10.251"
 Port: 1201
+RateLimitPolicy: "drop"
+StateFile: "/var/lib/aodv/state"
+StateSaveInterval: 2000 # milliseconds
+BufferMaxBytes: 4096
+BufferMaxPackets: 8
+BufferDropPolicy: "newest"
+QuarantineThreshold: 20
+QuarantineTime: 60000 # milliseconds
+MTU: 1400
+AggregateMessages: true
+CaptureFile: "/tmp/aodv.pcap"
 ACTIVE_ROUTE_TIMEOUT: 3001 # milliseconds
 ALLOWED_HELLO_LOSS: 3
 HELLO_INTERVAL: 1001 # milliseconds
@@ -298,6 +442,17 @@ TTL_THRESHOLD: 8
         broadcast_address: Ipv4Addr::new(192, 168, 10, 251),
         current_ip: config1.current_ip,
         port: 1201,
+        rate_limit_policy: RateLimitPolicy::Drop,
+        state_file: Some(String::from("/var/lib/aodv/state")),
+        state_save_interval: Duration::milliseconds(2000),
+        buffer_max_bytes: 4096,
+        buffer_max_packets: 8,
+        buffer_drop_policy: DropPolicy::Newest,
+        quarantine_threshold: 20,
+        quarantine_time: Duration::milliseconds(60_000),
+        mtu: 1400,
+        aggregate_messages: true,
+        capture_file: Some(String::from("/tmp/aodv.pcap")),
         ACTIVE_ROUTE_TIMEOUT: Duration::milliseconds(3001),
         ALLOWED_HELLO_LOSS: 3,
         BLACKLIST_TIMEOUT: Duration::milliseconds(8856),
@@ -328,3 +483,48 @@ TTL_THRESHOLD: 8
     // Clean up tmp file
     remove_file(tmp).unwrap();
 }
+
+#[test]
+fn test_negative_save_interval() {
+    use std::env::temp_dir;
+    use std::fs::{remove_file, File};
+
+    let mut tmp = temp_dir();
+    tmp.push("negative_interval.yaml");
+    {
+        let mut c = File::create(&tmp).unwrap();
+        c.write_all(b"StateSaveInterval: -5000\n").unwrap();
+    }
+
+    let mut config = Config::default();
+    config.read_config(File::open(&tmp).unwrap());
+    assert_eq!(
+        config.state_save_interval,
+        Config::default().state_save_interval
+    );
+
+    remove_file(tmp).unwrap();
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_json() {
+    use serde_json;
+
+    let config = Config {
+        HELLO_INTERVAL: Duration::milliseconds(1500),
+        state_file: Some(String::from("/var/lib/aodv/state")),
+        ..Config::default()
+    };
+    let json = serde_json::to_value(&config).unwrap();
+    assert_eq!(json["HELLO_INTERVAL"], 1500);
+    assert_eq!(json["broadcast_address"], "255.255.255.255");
+    assert_eq!(json["rate_limit_policy"], "queue");
+    assert_eq!(serde_json::from_value::<Config>(json).unwrap(), config);
+
+    // Anything left out keeps its default
+    let config: Config = serde_json::from_str(r#"{"mtu": 1280, "DELETE_PERIOD": 20000}"#).unwrap();
+    assert_eq!(config.mtu, 1280);
+    assert_eq!(config.DELETE_PERIOD, Duration::seconds(20));
+    assert_eq!(config.HELLO_INTERVAL, Config::default().HELLO_INTERVAL);
+}
@@ -0,0 +1,460 @@
+use std::fmt::{self, Write};
+use std::fs;
+use std::io::{self, Read};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::{AodvMessage, Extension, ParseError, RERR, RREP, RREQ};
+
+const BORDER: &str = "+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+";
+
+/// Return the bytes to decode: the contents of `file` if given, else the hex in `hex`, else the
+/// hex on stdin
+pub fn input(hex: Option<&str>, file: Option<&str>) -> io::Result<Vec<u8>> {
+    if let Some(path) = file {
+        return fs::read(path);
+    }
+    let hex = match hex {
+        Some(hex) => String::from(hex),
+        None => {
+            let mut hex = String::new();
+            io::stdin().read_to_string(&mut hex)?;
+            hex
+        }
+    };
+    parse_hex(&hex).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Parse hex as it gets pasted into bug reports: bare (`01a80090`), spaced, colon separated,
+/// with `0x` prefixes, or as the output of `hexdump -C`, `xxd` or Wireshark with their offsets
+/// and ASCII columns
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for line in s.lines() {
+        // hexdump -C puts the ASCII between bars
+        let line = line.split('|').next().unwrap_or("");
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let dump = words.len() > 1 && is_offset(line, words[0], words[1]);
+        if dump {
+            words.remove(0);
+        }
+        for word in words {
+            match hex_word(word) {
+                Some(b) => bytes.extend(b),
+                // Whatever follows the bytes of a dump line is its ASCII column
+                None if dump => break,
+                None => return Err(format!("{:?} isn't hex", word)),
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Return whether `word` is the offset at the start of a dump `line` followed by `next`.
+///
+/// xxd ends its offsets with a colon. hexdump -C and Wireshark don't, so their offset has to count
+/// whole rows and be set off from single bytes by at least two spaces.
+fn is_offset(line: &str, word: &str, next: &str) -> bool {
+    if word.ends_with(':') {
+        return true;
+    }
+    let row = (word.len() == 4 || word.len() == 8)
+        && u32::from_str_radix(word, 16).is_ok_and(|offset| offset % 16 == 0);
+    row && next.len() == 2
+        && hex_word(next).is_some()
+        && line.trim_start()[word.len()..].starts_with("  ")
+}
+
+fn hex_word(word: &str) -> Option<Vec<u8>> {
+    let word = word.trim_start_matches("0x");
+    let digits: String = word
+        .chars()
+        .filter(|&c| c != ':' && c != '-' && c != ',')
+        .collect();
+    if digits.is_empty() || digits.len() % 2 == 1 || !digits.is_ascii() {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Why some bytes couldn't be decoded, and where
+#[derive(Debug)]
+pub struct Malformed {
+    pub offset: usize,     // The first byte that doesn't fit
+    pub error: ParseError, // What was wrong with it
+    pub dissected: String, // Breakdown of the messages before it
+    bytes: Vec<u8>,
+}
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.dissected)?;
+        writeln!(f, "Malformed at byte {}: {}", self.offset, self.error)?;
+
+        // The line of the dump with the failing byte in it, pointed out
+        let line = self.offset / 16 * 16;
+        let end = self.bytes.len().min(line + 16);
+        write!(f, "{:04x}:", line)?;
+        for b in &self.bytes[line.min(end)..end] {
+            write!(f, " {:02x}", b)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:>1$}", "^^", 8 + 3 * (self.offset - line))
+    }
+}
+
+/// Take a datagram apart message by message, drawing each like the diagrams of section 5. of
+/// the RFC
+pub fn dissect(b: &[u8]) -> Result<String, Malformed> {
+    let mut out = String::new();
+    let mut start = 0;
+    let malformed = |out: String, offset, error| Malformed {
+        offset,
+        error,
+        dissected: out,
+        bytes: b.to_vec(),
+    };
+    if b.is_empty() {
+        return Err(malformed(out, 0, ParseError::Empty));
+    }
+    while start < b.len() {
+        let rest = &b[start..];
+        let end = match AodvMessage::message_end(rest) {
+            Ok(end) if end <= rest.len() => end,
+            Ok(end) => {
+                let e = ParseError::Truncated {
+                    expected: end,
+                    got: rest.len(),
+                };
+                return Err(malformed(out, b.len(), e));
+            }
+            Err(e) => return Err(malformed(out, start + failing_offset(rest, &e), e)),
+        };
+        let m = &rest[..end];
+        match AodvMessage::parse(m) {
+            Ok(msg) => draw(&mut out, start, m, &msg),
+            Err(e) => return Err(malformed(out, start + failing_offset(m, &e), e)),
+        }
+        start += end;
+    }
+    Ok(out)
+}
+
+/// Return which byte of the message `m` parsing it failed on
+fn failing_offset(m: &[u8], e: &ParseError) -> usize {
+    match *e {
+        ParseError::Empty | ParseError::UnknownType(_) | ParseError::WrongType { .. } => 0,
+        ParseError::Truncated { got, .. } => got,
+        ParseError::BadLength { .. } => AodvMessage::message_len(m).unwrap_or(0),
+        ParseError::DestCountMismatch { .. } => 3,
+        ParseError::BadExtension { .. } => {
+            let fixed = AodvMessage::message_len(m).unwrap_or(0);
+            fixed + bad_extension(&m[fixed.min(m.len())..])
+        }
+    }
+}
+
+/// Return where the first extension that doesn't check out starts
+fn bad_extension(b: &[u8]) -> usize {
+    let mut i = 0;
+    while i < b.len() {
+        let next = match b.get(i + 1) {
+            Some(&len) => i + 2 + len as usize,
+            None => return i,
+        };
+        if next > b.len() || Extension::check_all(&b[i..next]).is_err() {
+            return i;
+        }
+        i = next;
+    }
+    i
+}
+
+fn draw(out: &mut String, start: usize, m: &[u8], msg: &AodvMessage) {
+    let _ = writeln!(out, "{} at byte {}, {} bytes", msg.kind(), start, m.len());
+    let _ = writeln!(out, "{}", msg);
+    let _ = writeln!(out, "{}", BORDER);
+    match *msg {
+        AodvMessage::Rreq(ref r) => draw_rreq(out, start, m, r),
+        AodvMessage::Rrep(ref r) | AodvMessage::Hello(ref r) => draw_rrep(out, start, m, r),
+        AodvMessage::Rerr(ref r) => draw_rerr(out, start, m, r),
+        AodvMessage::Ack => row(
+            out,
+            start,
+            &m[..2],
+            &[(8, String::from("Type 4")), (8, String::from("Reserved"))],
+        ),
+    }
+    draw_extensions(out, start, m);
+    out.push('\n');
+}
+
+fn draw_rreq(out: &mut String, start: usize, m: &[u8], r: &RREQ) {
+    row(
+        out,
+        start,
+        &m[..4],
+        &[
+            (8, String::from("Type 1")),
+            (1, flag(r.j, "J")),
+            (1, flag(r.r, "R")),
+            (1, flag(r.g, "G")),
+            (1, flag(r.d, "D")),
+            (1, flag(r.u, "U")),
+            (
+                11,
+                format!("Reserved {}", BigEndian::read_u16(&m[1..3]) & 0x07ff),
+            ),
+            (8, format!("Hop Count {}", r.hop_count)),
+        ],
+    );
+    word(out, start + 4, &m[4..8], format!("RREQ ID {}", r.rreq_id));
+    word(
+        out,
+        start + 8,
+        &m[8..12],
+        format!("Destination IP Address {}", r.dest_ip),
+    );
+    word(
+        out,
+        start + 12,
+        &m[12..16],
+        format!("Destination Sequence Number {}", r.dest_seq_num),
+    );
+    word(
+        out,
+        start + 16,
+        &m[16..20],
+        format!("Originator IP Address {}", r.orig_ip),
+    );
+    word(
+        out,
+        start + 20,
+        &m[20..24],
+        format!("Originator Sequence Number {}", r.orig_seq_num),
+    );
+}
+
+fn draw_rrep(out: &mut String, start: usize, m: &[u8], r: &RREP) {
+    row(
+        out,
+        start,
+        &m[..4],
+        &[
+            (8, String::from("Type 2")),
+            (1, flag(r.r, "R")),
+            (1, flag(r.a, "A")),
+            (
+                9,
+                format!("Reserved {}", BigEndian::read_u16(&m[1..3]) >> 5 & 0x01ff),
+            ),
+            (5, format!("Pfx {}", r.prefix_size)),
+            (8, format!("Hop Count {}", r.hop_count)),
+        ],
+    );
+    word(
+        out,
+        start + 4,
+        &m[4..8],
+        format!("Destination IP Address {}", r.dest_ip),
+    );
+    word(
+        out,
+        start + 8,
+        &m[8..12],
+        format!("Destination Sequence Number {}", r.dest_seq_num),
+    );
+    word(
+        out,
+        start + 12,
+        &m[12..16],
+        format!("Originator IP Address {}", r.orig_ip),
+    );
+    word(
+        out,
+        start + 16,
+        &m[16..20],
+        format!("Lifetime {} ms", r.lifetime),
+    );
+}
+
+fn draw_rerr(out: &mut String, start: usize, m: &[u8], r: &RERR) {
+    row(
+        out,
+        start,
+        &m[..4],
+        &[
+            (8, String::from("Type 3")),
+            (1, flag(r.n, "N")),
+            (
+                15,
+                format!("Reserved {}", BigEndian::read_u16(&m[1..3]) & 0x7fff),
+            ),
+            (8, format!("DestCount {}", r.dest_count())),
+        ],
+    );
+    for (i, &(ip, seq)) in r.udests().iter().enumerate() {
+        let at = 4 + 8 * i;
+        word(
+            out,
+            start + at,
+            &m[at..at + 4],
+            format!("Unreachable Destination IP Address ({}) {}", i + 1, ip),
+        );
+        word(
+            out,
+            start + at + 4,
+            &m[at + 4..at + 8],
+            format!("Unreachable Dest Sequence Number ({}) {}", i + 1, seq),
+        );
+    }
+}
+
+/// Draw whatever follows the fixed part of a message, one extension per line
+fn draw_extensions(out: &mut String, start: usize, m: &[u8]) {
+    let mut i = AodvMessage::message_len(m).unwrap_or(m.len());
+    while i + 2 <= m.len() {
+        let next = i + 2 + m[i + 1] as usize;
+        let ext = match Extension::parse_all(&m[i..next]) {
+            Ok(mut exts) => exts.remove(0),
+            Err(_) => return,
+        };
+        let what = match ext {
+            Extension::HelloInterval(interval) => format!("Hello Interval {} ms", interval),
+            Extension::Timestamp(t) => format!("Timestamp {} ms", t),
+            Extension::Unknown { kind, .. } => format!("Unknown extension type {}", kind),
+        };
+        let _ = writeln!(
+            out,
+            "Extension {:<55}  {}",
+            what,
+            hex(start + i, &m[i..next])
+        );
+        i = next;
+    }
+}
+
+/// Draw a flag as its letter when set and a dot when not
+fn flag(set: bool, c: &str) -> String {
+    if set {
+        c.to_string()
+    } else {
+        ".".to_string()
+    }
+}
+
+/// Draw a 32-bit word holding a single field
+fn word(out: &mut String, offset: usize, bytes: &[u8], text: String) {
+    row(out, offset, bytes, &[(32, text)]);
+}
+
+/// Draw one row of a diagram, each field `bits` wide, with its bytes alongside
+fn row(out: &mut String, offset: usize, bytes: &[u8], fields: &[(usize, String)]) {
+    out.push('|');
+    for &(bits, ref text) in fields {
+        let _ = write!(out, "{:^1$}|", text, bits * 2 - 1);
+    }
+    let _ = writeln!(out, "  {}", hex(offset, bytes));
+    let bits: usize = fields.iter().map(|f| f.0).sum();
+    let _ = writeln!(out, "{}", &BORDER[..bits * 2 + 1]);
+}
+
+fn hex(offset: usize, bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{:04x}: {}", offset, bytes.join(" "))
+}
+
+#[cfg(test)]
+mod test_decode {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    fn ip(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    const RREQ: &str = "01a8009000003859c0a80a0e0000000cc0a80a130000003f";
+
+    #[test]
+    fn test_parse_hex() {
+        let bytes = parse_hex(RREQ).unwrap();
+        assert_eq!(bytes.len(), 24);
+        assert_eq!(
+            parse_hex("01 a8:00 0x90\n00-00").unwrap(),
+            vec![1, 0xa8, 0, 0x90, 0, 0]
+        );
+
+        // hexdump -C, xxd and Wireshark all prefix an offset and add the ASCII
+        let hexdump =
+            "00000000  01 a8 00 90 00 00 38 59  c0 a8 0a 0e 00 00 00 0c  |......8Y........|\n\
+                       00000010  c0 a8 0a 13 00 00 00 3f                           |.......?|\n";
+        assert_eq!(parse_hex(hexdump).unwrap(), bytes);
+        let xxd = "00000000: 01a8 0090 0000 3859 c0a8 0a0e 0000 000c  ......8Y........\n\
+                   00000010: c0a8 0a13 0000 003f                      .......?\n";
+        assert_eq!(parse_hex(xxd).unwrap(), bytes);
+        let wireshark =
+            "0000   01 a8 00 90 00 00 38 59 c0 a8 0a 0e 00 00 00 0c   ......8Y........\n\
+                         0010   c0 a8 0a 13 00 00 00 3f                           .......?\n";
+        assert_eq!(parse_hex(wireshark).unwrap(), bytes);
+
+        // Bare hex that happens to start with what could pass for an offset keeps every byte
+        assert_eq!(
+            parse_hex("0200 0000 c0a8 0a0e").unwrap(),
+            vec![2, 0, 0, 0, 0xc0, 0xa8, 0x0a, 0x0e]
+        );
+        assert_eq!(
+            parse_hex("0200 00 01\n0010 02 03").unwrap(),
+            vec![2, 0, 0, 1, 0, 0x10, 2, 3]
+        );
+
+        assert!(parse_hex("01 zz").is_err());
+        assert!(parse_hex("0 1").is_err());
+    }
+
+    #[test]
+    fn test_dissect() {
+        let mut b = vec![4, 0];
+        b.extend(parse_hex(RREQ).unwrap());
+        let text = dissect(&b).unwrap();
+        assert!(text.starts_with("RREP-ACK at byte 0, 2 bytes\n"));
+        assert!(text.contains("RREQ at byte 2, 24 bytes\n"));
+        assert!(text.contains("|    Type 1     |J|.|G|.|U|     Reserved 0      | Hop Count 144 |  0002: 01 a8 00 90\n"));
+        assert!(text.contains(&format!("Destination IP Address {}", ip("192.168.10.14"))));
+
+        let rerr = parse_hex("03000002 c0a80a12 00075dc3 ffffffff 00000000 0104000003e8").unwrap();
+        let text = dissect(&rerr).unwrap();
+        assert!(text.contains("DestCount 2"));
+        assert!(text.contains("Unreachable Dest Sequence Number (1) 482755"));
+        assert!(text.contains("Extension Hello Interval 1000 ms"));
+    }
+
+    #[test]
+    fn test_failing_offset() {
+        let rreq = parse_hex(RREQ).unwrap();
+        let offset = |b: &[u8]| dissect(b).unwrap_err().offset;
+
+        assert_eq!(offset(&rreq[..20]), 20);
+        assert_eq!(offset(&[9, 0]), 0);
+        // The count promises more destinations than follow
+        let rerr = [3, 0, 0, 2, 192, 168, 10, 18, 0, 7, 93, 195];
+        assert_eq!(offset(&rerr), 12);
+        // After a RREQ the short RERR can only be extensions, the third of which runs over
+        let mut b = rreq.clone();
+        b.extend_from_slice(&rerr);
+        assert_eq!(offset(&b), 24 + 6);
+        // A Hello Interval extension cut short
+        let mut b = rreq.clone();
+        b.extend_from_slice(&[200, 0, 1, 4, 0, 0]);
+        let malformed = dissect(&b).unwrap_err();
+        assert_eq!(malformed.offset, 26);
+        assert_eq!(
+            malformed.error,
+            ParseError::BadExtension { kind: 1, len: 4 }
+        );
+        assert!(malformed.to_string().ends_with("0010: c0 a8 0a 13 00 00 00 3f c8 00 01 04 00 00\n                                    ^^\n"));
+    }
+}
@@ -0,0 +1,133 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Utc};
+
+use std::net::Ipv4Addr;
+
+use super::config::Config;
+
+/// An outstanding route discovery for a single destination as per sections 6.3 and 6.4 of the RFC
+#[derive(Clone, Debug, PartialEq)]
+pub struct Discovery {
+    pub dest_ip: Ipv4Addr,       // Destination we are looking for
+    pub ttl: usize,              // IP TTL of the last RREQ sent
+    pub retries: usize,          // RREQs sent at NET_DIAMETER so far
+    pub deadline: DateTime<Utc>, // When to give up on the last RREQ
+}
+
+impl Discovery {
+    /// Start a discovery, expanding from `ttl` if the last known hop count is given
+    pub fn new(dest_ip: Ipv4Addr, last_hop_count: Option<u8>, config: &Config) -> Self {
+        let ttl = match last_hop_count {
+            Some(hops) => hops as usize + config.TTL_INCREMENT,
+            None => config.TTL_START,
+        };
+        let mut discovery = Discovery {
+            dest_ip,
+            ttl: 0,
+            retries: 0,
+            deadline: Utc::now(),
+        };
+        discovery.set_ttl(ttl, config);
+        discovery
+    }
+    /// Return whether the last RREQ has gone unanswered for too long
+    pub fn expired(&self) -> bool {
+        self.deadline <= Utc::now()
+    }
+    /// Move on to the next ring or retry, returning false once RREQ_RETRIES is exhausted
+    pub fn next_attempt(&mut self, config: &Config) -> bool {
+        if self.ttl < config.NET_DIAMETER {
+            let ttl = self.ttl + config.TTL_INCREMENT;
+            self.set_ttl(ttl, config);
+            return true;
+        }
+        if self.retries >= config.RREQ_RETRIES {
+            return false;
+        }
+        self.retries += 1;
+        // Binary exponential backoff
+        self.deadline = Utc::now() + config.NET_TRAVERSAL_TIME * (1 << self.retries);
+        true
+    }
+    /// Use `ttl` for the next RREQ, jumping to NET_DIAMETER past TTL_THRESHOLD
+    fn set_ttl(&mut self, ttl: usize, config: &Config) {
+        let (ttl, wait) = if ttl > config.TTL_THRESHOLD {
+            (config.NET_DIAMETER, config.NET_TRAVERSAL_TIME)
+        } else {
+            (ttl, config.ring_traversal_time(ttl))
+        };
+        self.ttl = ttl;
+        self.deadline = Utc::now() + wait;
+    }
+}
+
+/// A local repair of a broken route as per section 6.12 of the RFC
+#[derive(Clone, Debug, PartialEq)]
+pub struct Repair {
+    pub dest_ip: Ipv4Addr,       // Destination of the broken route
+    pub hop_count: u8,           // Hop count of the route before it broke
+    pub ttl: usize,              // IP TTL of the repair RREQ
+    pub deadline: DateTime<Utc>, // When to give up on the repair
+}
+
+impl Repair {
+    /// Start repairing a route that was `hop_count` hops long, `hops_to_source` hops from the
+    /// originator of the data being forwarded (0 if unknown)
+    pub fn new(dest_ip: Ipv4Addr, hop_count: u8, hops_to_source: u8, config: &Config) -> Self {
+        // MIN_REPAIR_TTL should be the last known hop count to the destination
+        let min_repair_ttl = config.MIN_REPAIR_TTL.max(hop_count as usize);
+        let ttl = min_repair_ttl.max(hops_to_source as usize / 2) + config.LOCAL_ADD_TTL;
+        Repair {
+            dest_ip,
+            hop_count,
+            ttl,
+            deadline: Utc::now() + config.ring_traversal_time(ttl),
+        }
+    }
+    /// Return whether a route `hop_count` hops long is short enough to repair locally
+    pub fn possible(hop_count: u8, config: &Config) -> bool {
+        f64::from(hop_count) <= config.MAX_REPAIR_TTL
+    }
+    /// Return whether the repair RREQ has gone unanswered for too long
+    pub fn expired(&self) -> bool {
+        self.deadline <= Utc::now()
+    }
+}
+
+#[cfg(test)]
+mod test_discovery {
+    use super::*;
+
+    #[test]
+    fn test_expanding_ring() {
+        let config = Config::default();
+        let mut d = Discovery::new(Ipv4Addr::new(192, 168, 10, 14), None, &config);
+
+        let mut ttls = vec![d.ttl];
+        while d.next_attempt(&config) {
+            ttls.push(d.ttl);
+        }
+        // TTL_START 1, TTL_INCREMENT 2, TTL_THRESHOLD 7, NET_DIAMETER 35, RREQ_RETRIES 2
+        assert_eq!(ttls, vec![1, 3, 5, 7, 35, 35, 35]);
+        assert_eq!(d.retries, config.RREQ_RETRIES);
+    }
+
+    #[test]
+    fn test_known_hop_count() {
+        let config = Config::default();
+        let d = Discovery::new(Ipv4Addr::new(192, 168, 10, 14), Some(4), &config);
+        assert_eq!(d.ttl, 6);
+    }
+
+    #[test]
+    fn test_repair_ttl() {
+        let config = Config::default();
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        // max(MIN_REPAIR_TTL, 0.5 * #hops) + LOCAL_ADD_TTL
+        assert_eq!(Repair::new(dest, 3, 0, &config).ttl, 5);
+        assert_eq!(Repair::new(dest, 3, 10, &config).ttl, 7);
+        assert!(Repair::possible(10, &config));
+        assert!(!Repair::possible(11, &config));
+    }
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why some bytes aren't a valid aodv control message
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    Empty,                                         // Nothing to parse
+    UnknownType(u8),                               // The type byte isn't one of sections 5.1 to 5.4
+    Truncated { expected: usize, got: usize },     // Too short for its type
+    BadLength { msg_type: u8, len: usize },        // Too long or the wrong shape for its type
+    WrongType { expected: u8, got: u8 },           // Asked to parse one type, given another
+    DestCountMismatch { count: u8, found: usize }, // RERR count disagrees with its list
+    BadExtension { kind: u8, len: usize },         // Extension cut short
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ParseError::*;
+        match *self {
+            Empty => write!(f, "Buffer is empty"),
+            UnknownType(t) => write!(f, "Unknown message type {}", t),
+            Truncated { expected, got } => {
+                write!(
+                    f,
+                    "Message truncated: expected {} bytes, got {}",
+                    expected, got
+                )
+            }
+            BadLength { msg_type, len } => {
+                write!(f, "{} bytes is the wrong length for type {}", len, msg_type)
+            }
+            WrongType { expected, got } => {
+                write!(f, "Expected message type {}, got {}", expected, got)
+            }
+            BadExtension { kind, len } => {
+                write!(f, "Bad extension of type {} and length {}", kind, len)
+            }
+            DestCountMismatch { count, found } => write!(
+                f,
+                "RERR destination count is {} but {} destinations follow",
+                count, found
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Why a builder or constructor couldn't produce a valid message
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildError {
+    Missing(&'static str), // A field with no sensible default was never set
+    PrefixTooLong(u8),     // The prefix size doesn't fit in its 5 bits
+    TooManyDests(usize),   // More unreachable destinations than DestCount can announce
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::Missing(field) => write!(f, "No {} was given", field),
+            BuildError::PrefixTooLong(size) => {
+                write!(f, "Prefix size {} is more than 31 bits", size)
+            }
+            BuildError::TooManyDests(count) => {
+                write!(
+                    f,
+                    "{} unreachable destinations don't fit in one RERR",
+                    count
+                )
+            }
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+/// Why a line isn't a message in the text format of the `text` module
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextError {
+    UnknownKind(String),   // The first word isn't a message type
+    Missing(&'static str), // A field with no default isn't there
+    BadField(String),      // A field that doesn't parse or doesn't belong to the message type
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TextError::UnknownKind(ref kind) => write!(f, "Unknown message type {:?}", kind),
+            TextError::Missing(field) => write!(f, "Missing field {}", field),
+            TextError::BadField(ref field) => write!(f, "Bad field {:?}", field),
+        }
+    }
+}
+
+impl Error for TextError {}
+
+/// Anything that can go wrong reading or writing aodv control messages
+#[derive(Debug)]
+pub enum AodvError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for AodvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AodvError::Io(ref e) => write!(f, "{}", e),
+            AodvError::Parse(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for AodvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            AodvError::Io(ref e) => Some(e),
+            AodvError::Parse(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for AodvError {
+    fn from(e: io::Error) -> Self {
+        AodvError::Io(e)
+    }
+}
+
+impl From<ParseError> for AodvError {
+    fn from(e: ParseError) -> Self {
+        AodvError::Parse(e)
+    }
+}
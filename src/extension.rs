@@ -0,0 +1,180 @@
+use byteorder::{BigEndian, ByteOrder};
+use bytes::BufMut;
+
+use super::ParseError;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Type of the Hello Interval extension of section 6.9 of the RFC
+pub const HELLO_INTERVAL_EXT: u8 = 1;
+/// Type of the extension carrying when a message was sent. The RFC doesn't assign one, and other
+/// implementations already use the low numbers for their own, so it comes from the top.
+pub const TIMESTAMP_EXT: u8 = 254;
+
+///```text
+///Extension Format:
+///0                   1                   2                   3
+///0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///|     Type      |    Length     |  type-specific data ...
+///+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-
+///```
+/// An extension following the fixed part of a message as per section 5. of the RFC
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Extension {
+    HelloInterval(u32),                  // Milliseconds between the sender's Hellos
+    Timestamp(u64),                      // Milliseconds since the epoch
+    Unknown { kind: u8, data: Vec<u8> }, // Kept as is so it can be forwarded
+}
+
+impl Extension {
+    /// Parse the extensions filling the rest of a message
+    pub fn parse_all(b: &[u8]) -> Result<Vec<Extension>, ParseError> {
+        let mut extensions = Vec::new();
+        let mut i = 0;
+        while i < b.len() {
+            let (kind, data) = next(&b[i..])?;
+            extensions.push(match (kind, data.len()) {
+                (HELLO_INTERVAL_EXT, 4) => Extension::HelloInterval(BigEndian::read_u32(data)),
+                (TIMESTAMP_EXT, 8) => Extension::Timestamp(BigEndian::read_u64(data)),
+                _ => Extension::Unknown {
+                    kind,
+                    data: data.to_vec(),
+                },
+            });
+            i += 2 + data.len();
+        }
+        Ok(extensions)
+    }
+    /// Check the extensions filling the rest of a message without keeping them
+    pub fn check_all(b: &[u8]) -> Result<(), ParseError> {
+        let mut i = 0;
+        while i < b.len() {
+            i += 2 + next(&b[i..])?.1.len();
+        }
+        Ok(())
+    }
+    /// Return the type byte of the extension
+    pub fn kind(&self) -> u8 {
+        match *self {
+            Extension::HelloInterval(_) => HELLO_INTERVAL_EXT,
+            Extension::Timestamp(_) => TIMESTAMP_EXT,
+            Extension::Unknown { kind, .. } => kind,
+        }
+    }
+    /// Return how many bytes the extension takes up, type and length included
+    pub fn encoded_len(&self) -> usize {
+        2 + match *self {
+            Extension::HelloInterval(_) => 4,
+            Extension::Timestamp(_) => 8,
+            Extension::Unknown { ref data, .. } => data.len(),
+        }
+    }
+    /// Write the bit field representation of the extension to `buf`
+    pub fn encode_into(&self, buf: &mut impl BufMut) {
+        buf.put_u8(self.kind());
+        buf.put_u8((self.encoded_len() - 2) as u8);
+        match *self {
+            Extension::HelloInterval(interval) => buf.put_u32_be(interval),
+            Extension::Timestamp(t) => buf.put_u64_be(t),
+            Extension::Unknown { ref data, .. } => buf.put_slice(data),
+        }
+    }
+    /// Return the bit field representation of the extension
+    pub fn bit_message(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut b);
+        b
+    }
+}
+
+/// Split the type and data of the extension at the start of `b` off, checking it isn't cut short.
+///
+/// Any length is accepted for any type; one we can't decode is kept as `Extension::Unknown`.
+fn next(b: &[u8]) -> Result<(u8, &[u8]), ParseError> {
+    let kind = b[0];
+    let len = match b.get(1) {
+        Some(&len) => len as usize,
+        None => return Err(ParseError::BadExtension { kind, len: 0 }),
+    };
+    match b.get(2..2 + len) {
+        Some(data) => Ok((kind, data)),
+        None => Err(ParseError::BadExtension { kind, len }),
+    }
+}
+
+/// Write every extension to `buf`
+pub fn write_all(buf: &mut impl BufMut, extensions: &[Extension]) {
+    for ext in extensions {
+        ext.encode_into(buf);
+    }
+}
+
+/// Return how many bytes `extensions` take up
+pub fn encoded_len(extensions: &[Extension]) -> usize {
+    extensions.iter().map(Extension::encoded_len).sum()
+}
+
+#[cfg(test)]
+mod test_extension {
+    use super::*;
+
+    #[test]
+    fn test_extension_encoding() {
+        let extensions = vec![
+            Extension::HelloInterval(1000),
+            Extension::Unknown {
+                kind: 200,
+                data: vec![1, 2, 3],
+            },
+            Extension::Timestamp(1_508_437_200_000),
+        ];
+        let bytes: &[u8] = &[
+            1, 4, 0, 0, 3, 232, 200, 3, 1, 2, 3, 254, 8, 0, 0, 1, 95, 53, 221, 12, 128,
+        ];
+        let mut b = Vec::new();
+        write_all(&mut b, &extensions);
+        assert_eq!(b, bytes);
+        assert_eq!(Extension::parse_all(bytes).unwrap(), extensions);
+    }
+
+    #[test]
+    fn test_bad_extensions() {
+        assert_eq!(
+            Extension::parse_all(&[200, 3, 1, 2]),
+            Err(ParseError::BadExtension { kind: 200, len: 3 })
+        );
+        assert_eq!(
+            Extension::parse_all(&[1, 4, 3, 232]),
+            Err(ParseError::BadExtension { kind: 1, len: 4 })
+        );
+        assert_eq!(
+            Extension::parse_all(&[7]),
+            Err(ParseError::BadExtension { kind: 7, len: 0 })
+        );
+    }
+
+    #[test]
+    fn test_unexpected_lengths() {
+        // Other implementations' type 2 and a Hello Interval of the wrong size are kept as is
+        let bytes: &[u8] = &[2, 4, 1, 2, 3, 4, 1, 2, 3, 232];
+        let extensions = Extension::parse_all(bytes).unwrap();
+        assert_eq!(
+            extensions,
+            vec![
+                Extension::Unknown {
+                    kind: 2,
+                    data: vec![1, 2, 3, 4],
+                },
+                Extension::Unknown {
+                    kind: 1,
+                    data: vec![3, 232],
+                },
+            ]
+        );
+        let mut b = Vec::new();
+        write_all(&mut b, &extensions);
+        assert_eq!(b, bytes);
+    }
+}
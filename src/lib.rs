@@ -1,25 +1,54 @@
 extern crate byteorder;
 extern crate bytes;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 extern crate tokio_io;
 
-use std::io;
+use std::fmt;
 use std::net::{Ipv4Addr, SocketAddr};
 
-use byteorder::{BigEndian, ByteOrder};
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{BufMut, BytesMut};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use tokio_io::codec::{Decoder, Encoder};
 
+pub use builder::{RrepBuilder, RreqBuilder, RreqFlags};
+pub use error::{AodvError, BuildError, ParseError, TextError};
+pub use extension::Extension;
+use node::{sender, Node};
+pub use view::{RerrView, RrepView, RreqView};
+
+pub mod buffer;
+pub mod builder;
 pub mod config;
+pub mod decode;
+pub mod discovery;
+pub mod error;
+pub mod extension;
+pub mod node;
+pub mod pcap;
+pub mod quarantine;
+pub mod ratelimit;
+pub mod routing;
+pub mod seq;
+#[cfg(feature = "serde")]
+pub mod serialize;
 pub mod server;
-
-#[macro_use]
-mod util;
+pub mod socket;
+pub mod state;
+pub mod text;
+pub mod view;
 
 pub const AODV_PORT: u16 = 654;
 pub const INSTANCE_PORT: u16 = 15_292;
+/// Size of the IPv4 and UDP headers in front of every datagram
+pub const HEADER_LEN: usize = 28;
 
 /// The enum for every sort of aodv control message
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(tag = "type"))]
 pub enum AodvMessage {
     Rreq(RREQ),
     Rrep(RREP),
@@ -28,46 +57,211 @@ pub enum AodvMessage {
     Ack,
 }
 
+/// What sort of aodv control message something is, without its contents
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MessageKind {
+    Rreq,
+    Rrep,
+    Rerr,
+    Hello,
+    Ack,
+}
+
+impl fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            MessageKind::Rreq => "RREQ",
+            MessageKind::Rrep => "RREP",
+            MessageKind::Rerr => "RERR",
+            MessageKind::Hello => "HELLO",
+            MessageKind::Ack => "RREP-ACK",
+        })
+    }
+}
+
 /// This mostly just uses pattern matching to call the struct method corresponding to its enum
 impl AodvMessage {
-    /// Try to convert bytes into an aodv message struct or return a ParseError
-    pub fn parse(b: &[u8]) -> Result<Self, io::Error> {
+    /// Try to convert bytes into an aodv message struct or return a ParseError.
+    ///
+    /// Without the IP TTL, any RREP shaped like a Hello is taken for one.
+    pub fn parse(b: &[u8]) -> Result<Self, ParseError> {
+        AodvMessage::parse_ttl(b, None)
+    }
+    /// Parse a message that arrived with IP TTL `ttl`, so that only a RREP sent with a TTL of 1
+    /// counts as a Hello as per section 6.9 of the RFC
+    pub fn parse_with_ttl(b: &[u8], ttl: u8) -> Result<Self, ParseError> {
+        AodvMessage::parse_ttl(b, Some(ttl))
+    }
+    fn parse_ttl(b: &[u8], ttl: Option<u8>) -> Result<Self, ParseError> {
         if b.is_empty() {
-            return Err(parse_error!("Buffer is empty"));
+            return Err(ParseError::Empty);
         }
         use self::AodvMessage::*;
-        // Type, Length, Multiple of 4 or not
-        match (b[0], b.len(), b.len() % 4) {
-            (1, 24, 0) => Ok(Rreq(RREQ::new(b)?)),
-            (2, 20, 0) => Ok(Rrep(RREP::new(b)?)),
-            (3, _, 0) => Ok(Rerr(RERR::new(b)?)),
-            (4, 2, 2) => Ok(Ack),
-            (_, _, _) => Err(parse_error!("Wrong length or type bit")),
+        match b[0] {
+            1 => Ok(Rreq(RREQ::new(b)?)),
+            2 => {
+                let r = RREP::new(b)?;
+                let hello = r.is_hello() && matches!(ttl, None | Some(1));
+                Ok(if hello { Hello(r) } else { Rrep(r) })
+            }
+            3 => Ok(Rerr(RERR::new(b)?)),
+            4 => {
+                check_len(b, 4, 2)?;
+                if b.len() > 2 {
+                    return Err(ParseError::BadLength {
+                        msg_type: 4,
+                        len: b.len(),
+                    });
+                }
+                Ok(Ack)
+            }
+            t => Err(ParseError::UnknownType(t)),
+        }
+    }
+    /// Parse every message in a datagram holding several back to back, as AODV-UU sends them
+    pub fn parse_all(b: &[u8]) -> Result<Vec<Self>, ParseError> {
+        AodvMessage::parse_all_ttl(b, None)
+    }
+    /// Parse every message in a datagram that arrived with IP TTL `ttl`
+    pub fn parse_all_with_ttl(b: &[u8], ttl: u8) -> Result<Vec<Self>, ParseError> {
+        AodvMessage::parse_all_ttl(b, Some(ttl))
+    }
+    /// Parse the messages in a datagram that arrived with IP TTL `ttl` one at a time, so the
+    /// ones before a malformed message can still be handled
+    pub fn parse_iter_with_ttl(b: &[u8], ttl: u8) -> Messages<'_> {
+        Messages {
+            rest: b,
+            ttl: Some(ttl),
+        }
+    }
+    fn parse_all_ttl(b: &[u8], ttl: Option<u8>) -> Result<Vec<Self>, ParseError> {
+        Messages { rest: b, ttl }.collect()
+    }
+    /// Return where the message at the start of `b` ends.
+    ///
+    /// Anything after its fixed part is taken as its extensions if it reads as a list of them,
+    /// and otherwise as the next message if it holds a whole one. `batch` never packs a message
+    /// with extensions alongside others, nor one message where it would read as extensions.
+    pub fn message_end(b: &[u8]) -> Result<usize, ParseError> {
+        let len = AodvMessage::message_len(b)?;
+        if b.len() <= len || Extension::check_all(&b[len..]).is_ok() {
+            return Ok(len.max(b.len()));
+        }
+        match AodvMessage::message_len(&b[len..]) {
+            Ok(next) if b.len() - len >= next => Ok(len),
+            _ => Ok(b.len()),
+        }
+    }
+    /// Return how long the fixed part of the message at the start of `b` is, going by its type
+    /// and header
+    pub fn message_len(b: &[u8]) -> Result<usize, ParseError> {
+        match b.first() {
+            None => Err(ParseError::Empty),
+            Some(1) => Ok(24),
+            Some(2) => Ok(20),
+            Some(3) if b.len() < 4 => Err(ParseError::Truncated {
+                expected: 4,
+                got: b.len(),
+            }),
+            Some(3) => Ok(4 + 8 * b[3] as usize),
+            Some(4) => Ok(2),
+            Some(&t) => Err(ParseError::UnknownType(t)),
+        }
+    }
+    /// Return what sort of message this is
+    pub fn kind(&self) -> MessageKind {
+        match *self {
+            AodvMessage::Rreq(_) => MessageKind::Rreq,
+            AodvMessage::Rrep(_) => MessageKind::Rrep,
+            AodvMessage::Rerr(_) => MessageKind::Rerr,
+            AodvMessage::Hello(_) => MessageKind::Hello,
+            AodvMessage::Ack => MessageKind::Ack,
+        }
+    }
+    /// Return the extensions following the message
+    pub fn extensions(&self) -> &[Extension] {
+        match *self {
+            AodvMessage::Rreq(ref r) => &r.extensions,
+            AodvMessage::Rrep(ref r) | AodvMessage::Hello(ref r) => &r.extensions,
+            AodvMessage::Rerr(ref r) => &r.extensions,
+            AodvMessage::Ack => &[],
         }
     }
     /// Convert an aodv control message into its representation as a bitfield
     pub fn bit_message(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut b);
+        b
+    }
+    /// Write the bit field representation of the message to `buf`
+    pub fn encode_into(&self, buf: &mut impl BufMut) {
         use self::AodvMessage::*;
         match *self {
-            Rreq(ref r) => r.bit_message(),
-            Rrep(ref r) | Hello(ref r) => r.bit_message(),
-            Rerr(ref r) => r.bit_message(),
-            Ack => vec![4, 0],
+            Rreq(ref r) => r.encode_into(buf),
+            Rrep(ref r) | Hello(ref r) => r.encode_into(buf),
+            Rerr(ref r) => r.encode_into(buf),
+            Ack => buf.put_slice(&[4, 0]),
+        }
+    }
+    /// Return how many bytes the message takes up on the wire
+    pub fn encoded_len(&self) -> usize {
+        use self::AodvMessage::*;
+        match *self {
+            Rreq(ref r) => r.encoded_len(),
+            Rrep(ref r) | Hello(ref r) => r.encoded_len(),
+            Rerr(ref r) => r.encoded_len(),
+            Ack => 2,
         }
     }
 
-    /// Handle a given aodv control message according to the protocol
-    pub fn handle_message(self, addr: &SocketAddr) {
+    /// Handle a given aodv control message that arrived with IP TTL `ttl` according to the
+    /// protocol
+    pub fn handle_message(self, addr: &SocketAddr, ttl: u8, node: &mut Node) {
         use self::AodvMessage::*;
         match self {
-            Rreq(mut r) => r.handle_message(addr),
-            Rrep(mut r) => r.handle_message(addr),
-            Rerr(mut r) => r.handle_message(addr),
-            Hello(mut r) => r.handle_message(addr),
+            Rreq(mut r) => r.handle_message(addr, ttl, node),
+            Rrep(mut r) => r.handle_message(addr, node),
+            Rerr(mut r) => r.handle_message(addr, node),
+            Hello(r) => r.handle_hello(addr, node),
             Ack => {
-                println!("Received Ack from {}", addr);
+                if let Some(neighbour) = sender(addr) {
+                    node.handle_ack(neighbour);
+                }
+            }
+        }
+    }
+}
+
+/// The messages of a datagram, parsed one after another up to the first malformed one
+pub struct Messages<'a> {
+    rest: &'a [u8],
+    ttl: Option<u8>,
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<AodvMessage, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let parsed = AodvMessage::message_end(self.rest).and_then(|len| {
+            if self.rest.len() < len {
+                return Err(ParseError::Truncated {
+                    expected: len,
+                    got: self.rest.len(),
+                });
             }
+            let msg = AodvMessage::parse_ttl(&self.rest[..len], self.ttl)?;
+            self.rest = &self.rest[len..];
+            Ok(msg)
+        });
+        // Nothing after a bad message can be trusted to start where it seems to
+        if parsed.is_err() {
+            self.rest = &[];
         }
+        Some(parsed)
     }
 }
 
@@ -76,28 +270,132 @@ pub struct AodvCodec;
 
 impl Encoder for AodvCodec {
     type Item = AodvMessage;
-    type Error = io::Error;
+    type Error = AodvError;
 
-    fn encode(&mut self, msg: AodvMessage, buf: &mut BytesMut) -> Result<(), io::Error> {
-        let msg = msg.bit_message();
-        buf.reserve(msg.len());
-        buf.put(msg);
+    fn encode(&mut self, msg: AodvMessage, buf: &mut BytesMut) -> Result<(), AodvError> {
+        buf.reserve(msg.encoded_len());
+        msg.encode_into(buf);
         Ok(())
     }
 }
 
 impl Decoder for AodvCodec {
     type Item = AodvMessage;
-    type Error = io::Error;
+    type Error = AodvError;
 
+    /// Split the next message off the front of `src`.
+    ///
+    /// A datagram can't be resynchronised after a bad message, so on an error the rest of it is
+    /// dropped.
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        match AodvMessage::parse(&src) {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        let len = match AodvMessage::message_end(src) {
+            Ok(len) => len,
+            Err(e) => {
+                src.clear();
+                return Err(e.into());
+            }
+        };
+        if src.len() < len {
+            return Ok(None);
+        }
+        let msg = src.split_to(len);
+        match AodvMessage::parse(&msg) {
             Ok(msg) => Ok(Some(msg)),
-            Err(e) => Err(e),
+            Err(e) => {
+                src.clear();
+                Err(e.into())
+            }
+        }
+    }
+    /// Every datagram ends where its last message does, so anything left over is truncated
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(msg) => Ok(Some(msg)),
+            None if src.is_empty() => Ok(None),
+            None => {
+                let err = ParseError::Truncated {
+                    expected: AodvMessage::message_len(src)?,
+                    got: src.len(),
+                };
+                src.clear();
+                Err(err.into())
+            }
         }
     }
 }
 
+/// Pack `msgs` into as few datagrams as possible with none over `max_len` bytes.
+///
+/// A message that is longer than `max_len` by itself still gets a datagram of its own, so a
+/// `max_len` of 0 sends every message separately. So do messages with extensions, and any
+/// message that `message_end` would take for the extensions of the one before it, as the
+/// receiver couldn't tell where they end otherwise.
+pub fn batch<'a, I>(msgs: I, max_len: usize) -> Vec<BytesMut>
+where
+    I: IntoIterator<Item = &'a AodvMessage>,
+{
+    let mut datagrams: Vec<BytesMut> = Vec::new();
+    let mut ends = Vec::new(); // Where each message in the last datagram ends
+    let mut open = false; // Whether the last datagram can take another message
+    for msg in msgs {
+        let len = msg.encoded_len();
+        let alone = !msg.extensions().is_empty();
+        let joined = match datagrams.last_mut() {
+            Some(d) if open && !alone && d.len() + len <= max_len => {
+                let start = d.len();
+                // BytesMut doesn't grow by itself as it is written to
+                d.reserve(len);
+                msg.encode_into(d);
+                ends.push(d.len());
+                if !splits_at(d, &ends) {
+                    d.truncate(start);
+                    ends.pop();
+                }
+                d.len() > start
+            }
+            _ => false,
+        };
+        if !joined {
+            let mut d = BytesMut::with_capacity(len);
+            msg.encode_into(&mut d);
+            datagrams.push(d);
+            ends = vec![len];
+        }
+        open = !alone;
+    }
+    datagrams
+}
+
+/// Return whether `message_end` splits `d` into messages ending at `ends`
+fn splits_at(d: &[u8], ends: &[usize]) -> bool {
+    let mut start = 0;
+    ends.iter().all(|&end| {
+        let ok = AodvMessage::message_end(&d[start..]) == Ok(end - start);
+        start = end;
+        ok
+    })
+}
+
+/// Check that `b` is a message of type `msg_type` at least `len` bytes long
+fn check_len(b: &[u8], msg_type: u8, len: usize) -> Result<(), ParseError> {
+    if b.len() < len {
+        return Err(ParseError::Truncated {
+            expected: len,
+            got: b.len(),
+        });
+    }
+    if b[0] != msg_type {
+        return Err(ParseError::WrongType {
+            expected: msg_type,
+            got: b[0],
+        });
+    }
+    Ok(())
+}
+
 ///```text
 ///RREQ Message Format:
 ///0                   1                   2                   3
@@ -117,11 +415,17 @@ impl Decoder for AodvCodec {
 ///+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 ///```
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RREQ {
+    #[cfg_attr(feature = "serde", serde(rename = "join"))]
     pub j: bool, // Join flag
+    #[cfg_attr(feature = "serde", serde(rename = "repair"))]
     pub r: bool, // Repair flag
+    #[cfg_attr(feature = "serde", serde(rename = "gratuitous"))]
     pub g: bool, // Gratuitous RREP flag
+    #[cfg_attr(feature = "serde", serde(rename = "dest_only"))]
     pub d: bool, // Destination Only flag
+    #[cfg_attr(feature = "serde", serde(rename = "unknown_seq_num"))]
     pub u: bool, // Unknown Sequence number
 
     pub hop_count: u8, // 8-bit Hop Count
@@ -132,39 +436,43 @@ pub struct RREQ {
 
     pub orig_ip: Ipv4Addr, // Originator IP Address
     pub orig_seq_num: u32, // Originator Sequence Number
+
+    pub extensions: Vec<Extension>, // Extensions following the message
 }
 
 impl RREQ {
+    /// Start building a RREQ, with no flags or extensions and a hop count and ID of 0
+    pub fn builder() -> RreqBuilder {
+        RreqBuilder::new()
+    }
     /// Return a RREQ message from a byte slice
-    pub fn new(b: &[u8]) -> Result<RREQ, io::Error> {
-        if b.len() != 24 {
-            return Err(parse_error!(format!(
-                "RREQ messages are 24 bytes, not {}",
-                b.len()
-            )));
-        }
-        if b[0] != 1 {
-            return Err(parse_error!("This is not a RREQ message"));
-        }
+    pub fn new(b: &[u8]) -> Result<RREQ, ParseError> {
+        let v = RreqView::new(b)?;
         Ok(RREQ {
-            j: 1 << 7 & b[1] != 0,
-            r: 1 << 6 & b[1] != 0,
-            g: 1 << 5 & b[1] != 0,
-            d: 1 << 4 & b[1] != 0,
-            u: 1 << 3 & b[1] != 0,
-            hop_count: b[3],
-            rreq_id: BigEndian::read_u32(&b[4..8]),
-            dest_ip: Ipv4Addr::new(b[8], b[9], b[10], b[11]),
-            dest_seq_num: BigEndian::read_u32(&b[12..16]),
-            orig_ip: Ipv4Addr::new(b[16], b[17], b[18], b[19]),
-            orig_seq_num: BigEndian::read_u32(&b[20..24]),
+            j: v.j(),
+            r: v.r(),
+            g: v.g(),
+            d: v.d(),
+            u: v.u(),
+            hop_count: v.hop_count(),
+            rreq_id: v.rreq_id(),
+            dest_ip: v.dest_ip(),
+            dest_seq_num: v.dest_seq_num(),
+            orig_ip: v.orig_ip(),
+            orig_seq_num: v.orig_seq_num(),
+            extensions: Extension::parse_all(v.extension_bytes())?,
         })
     }
     /// Return the bit field representation of a RREQ message
     pub fn bit_message(&self) -> Vec<u8> {
-        let mut b = Vec::with_capacity(24);
-        b.push(1);
-        b.push(
+        let mut b = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut b);
+        b
+    }
+    /// Write the bit field representation of a RREQ message to `buf`
+    pub fn encode_into(&self, buf: &mut impl BufMut) {
+        buf.put_u8(1);
+        buf.put_u8(
             // TODO use bitflags or 0b01000000 notation
             if self.j { 1 << 7 } else { 0 }
                 + if self.r { 1 << 6 } else { 0 }
@@ -172,30 +480,24 @@ impl RREQ {
                 + if self.d { 1 << 4 } else { 0 }
                 + if self.u { 1 << 3 } else { 0 },
         );
-        b.push(0); // Reserved space
-
-        b.push(self.hop_count);
-
-        // Buffer for writing big endian stuff
-        let mut buf = [0; 4];
-
-        BigEndian::write_u32(&mut buf, self.rreq_id);
-        b.extend(buf.iter());
-
-        b.extend(self.dest_ip.octets().iter());
-
-        BigEndian::write_u32(&mut buf, self.dest_seq_num);
-        b.extend(buf.iter());
-
-        b.extend(self.orig_ip.octets().iter());
-
-        BigEndian::write_u32(&mut buf, self.orig_seq_num);
-        b.extend(buf.iter());
-
-        b
+        buf.put_u8(0); // Reserved space
+        buf.put_u8(self.hop_count);
+        buf.put_u32_be(self.rreq_id);
+        buf.put_slice(&self.dest_ip.octets());
+        buf.put_u32_be(self.dest_seq_num);
+        buf.put_slice(&self.orig_ip.octets());
+        buf.put_u32_be(self.orig_seq_num);
+        extension::write_all(buf, &self.extensions);
     }
-    pub fn handle_message(&mut self, addr: &SocketAddr) {
-        unimplemented!();
+    /// Return how many bytes the RREQ takes up on the wire
+    pub fn encoded_len(&self) -> usize {
+        24 + extension::encoded_len(&self.extensions)
+    }
+    /// Update the routing table from a RREQ received from `addr` with IP TTL `ttl`
+    pub fn handle_message(&mut self, addr: &SocketAddr, ttl: u8, node: &mut Node) {
+        if let Some(prev_hop) = sender(addr) {
+            node.handle_rreq(self, prev_hop, ttl);
+        }
     }
 }
 
@@ -216,12 +518,16 @@ impl RREQ {
 ///+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 ///```
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RREP {
+    #[cfg_attr(feature = "serde", serde(rename = "repair"))]
     pub r: bool, // Repair flag
+    #[cfg_attr(feature = "serde", serde(rename = "ack_required"))]
     pub a: bool, // Acknowledgment required flag
 
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::prefix_size"))]
     pub prefix_size: u8, // 5-bit prefix size
-    pub hop_count: u8,   // 8-bit Hop Count
+    pub hop_count: u8, // 8-bit Hop Count
 
     pub dest_ip: Ipv4Addr, //Destination IP
     pub dest_seq_num: u32, //Destination Sequence Number
@@ -229,55 +535,111 @@ pub struct RREP {
     pub orig_ip: Ipv4Addr, //Originator IP
 
     pub lifetime: u32, //Lifetime in milliseconds
+
+    pub extensions: Vec<Extension>, // Extensions following the message
 }
 
 impl RREP {
-    /// Return a RREP message from a byte slice
-    pub fn new(b: &[u8]) -> Result<RREP, io::Error> {
-        if b.len() != 20 {
-            return Err(parse_error!(format!(
-                "RREP messages are 20 bytes, not {}",
-                b.len()
-            )));
+    /// Start building a RREP, with no flags or extensions and a hop count and lifetime of 0
+    pub fn builder() -> RrepBuilder {
+        RrepBuilder::new()
+    }
+    /// Return a Hello message advertising `own_ip` as per section 6.9 of the RFC
+    pub fn hello(own_ip: Ipv4Addr, seq_num: u32, lifetime: u32) -> RREP {
+        RREP {
+            r: false,
+            a: false,
+            prefix_size: 0,
+            hop_count: 0,
+            dest_ip: own_ip,
+            dest_seq_num: seq_num,
+            orig_ip: own_ip,
+            lifetime,
+            extensions: Vec::new(),
         }
-        if b[0] != 2 {
-            return Err(parse_error!("This is not a RREP message"));
+    }
+    /// Return a RREP answering `rreq` with a route `hop_count` hops long that stays valid for
+    /// `lifetime` milliseconds
+    pub fn reply_to(rreq: &RREQ, dest_seq_num: u32, hop_count: u8, lifetime: u32) -> RREP {
+        RREP {
+            r: false,
+            a: false,
+            prefix_size: 0,
+            hop_count,
+            dest_ip: rreq.dest_ip,
+            dest_seq_num,
+            orig_ip: rreq.orig_ip,
+            lifetime,
+            extensions: Vec::new(),
         }
+    }
+    /// Return a RREP message from a byte slice
+    pub fn new(b: &[u8]) -> Result<RREP, ParseError> {
+        let v = RrepView::new(b)?;
         Ok(RREP {
-            r: 1 << 7 & b[1] != 0,
-            a: 1 << 6 & b[1] != 0,
-            prefix_size: b[2] % 32,
-            hop_count: b[3],
-            dest_ip: Ipv4Addr::new(b[4], b[5], b[6], b[7]),
-            dest_seq_num: BigEndian::read_u32(&b[8..12]),
-            orig_ip: Ipv4Addr::new(b[12], b[13], b[14], b[15]),
-            lifetime: BigEndian::read_u32(&b[16..20]),
+            r: v.r(),
+            a: v.a(),
+            prefix_size: v.prefix_size(),
+            hop_count: v.hop_count(),
+            dest_ip: v.dest_ip(),
+            dest_seq_num: v.dest_seq_num(),
+            orig_ip: v.orig_ip(),
+            lifetime: v.lifetime(),
+            extensions: Extension::parse_all(v.extension_bytes())?,
         })
     }
     /// Return the bit field representation of a RREP message
     pub fn bit_message(&self) -> Vec<u8> {
-        let mut b = Vec::with_capacity(20);
-        b.push(2);
-        b.push(if self.r { 1 << 7 } else { 0 } + if self.a { 1 << 6 } else { 0 });
-        b.push(self.prefix_size % 32);
-        b.push(self.hop_count);
-
-        b.extend(self.dest_ip.octets().iter());
-
-        let mut buf = [0; 4];
-
-        BigEndian::write_u32(&mut buf, self.dest_seq_num);
-        b.extend(buf.iter());
-
-        b.extend(self.orig_ip.octets().iter());
-
-        BigEndian::write_u32(&mut buf, self.lifetime);
-        b.extend(buf.iter());
-
+        let mut b = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut b);
         b
     }
-    pub fn handle_message(&mut self, addr: &SocketAddr) {
-        unimplemented!();
+    /// Write the bit field representation of a RREP message to `buf`.
+    ///
+    /// Panics if `prefix_size` doesn't fit in its 5 bits, which `RREP::builder`, the parsers and
+    /// deserializing all refuse.
+    pub fn encode_into(&self, buf: &mut impl BufMut) {
+        assert!(
+            self.prefix_size <= 31,
+            "RREP prefix size {} doesn't fit in 5 bits",
+            self.prefix_size
+        );
+        buf.put_u8(2);
+        buf.put_u8(if self.r { 1 << 7 } else { 0 } + if self.a { 1 << 6 } else { 0 });
+        buf.put_u8(self.prefix_size);
+        buf.put_u8(self.hop_count);
+        buf.put_slice(&self.dest_ip.octets());
+        buf.put_u32_be(self.dest_seq_num);
+        buf.put_slice(&self.orig_ip.octets());
+        buf.put_u32_be(self.lifetime);
+        extension::write_all(buf, &self.extensions);
+    }
+    /// Return how many bytes the RREP takes up on the wire
+    pub fn encoded_len(&self) -> usize {
+        20 + extension::encoded_len(&self.extensions)
+    }
+    /// Return whether this RREP has the shape of a Hello message as per section 6.9 of the RFC
+    pub fn is_hello(&self) -> bool {
+        self.dest_ip == self.orig_ip && self.hop_count == 0
+    }
+    /// Return the Hello Interval extension's value, if the sender gave one
+    pub fn hello_interval(&self) -> Option<u32> {
+        self.extensions.iter().find_map(|ext| match *ext {
+            Extension::HelloInterval(interval) => Some(interval),
+            _ => None,
+        })
+    }
+    /// Update the routing table from a RREP received from `addr`
+    pub fn handle_message(&mut self, addr: &SocketAddr, node: &mut Node) {
+        if let Some(prev_hop) = sender(addr) {
+            node.handle_rrep(self, prev_hop);
+        }
+    }
+    /// Update the neighbour table from a Hello received from `addr`
+    pub fn handle_hello(&self, addr: &SocketAddr, node: &mut Node) {
+        if let Some(neighbour) = sender(addr) {
+            node.handle_hello(self, neighbour);
+        }
     }
 }
 
@@ -297,67 +659,111 @@ impl RREP {
 ///|Additional Unreachable Destination Sequence Numbers (if needed)|
 ///+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 ///```
+///
+/// The DestCount field is always the length of the list, which is kept between 1 and
+/// `RERR::MAX_DESTS` by only building a RERR through `new`, `for_dest`, `with_dests` or `split`.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RERR {
+    #[cfg_attr(feature = "serde", serde(rename = "no_delete"))]
     pub n: bool, // No delete flag
 
-    pub dest_count: u8, // 8-bit Destination Count
-
-    pub udest_list: Vec<(
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "udests", with = "::serialize::udests")
+    )]
+    udest_list: Vec<(
         Ipv4Addr, // Unreachable Destination IP Address
         u32,
     )>, // Unreachable Destination Sequence Number
+
+    pub extensions: Vec<Extension>, // Extensions following the message
 }
 
 impl RERR {
-    /// Return a RERR message from a byte slice
-    pub fn new(b: &[u8]) -> Result<RERR, io::Error> {
-        if (b.len() - 4) % 8 != 0 || b.len() < 12 {
-            return Err(parse_error!(
-                "This is not the right size for a RERR message"
-            ));
-        }
-        if b[0] != 3 {
-            return Err(parse_error!("This message is not a RERR message"));
-        }
-
-        let mut udest_list = Vec::new();
-        let mut i = 4;
-        while i < b.len() {
-            udest_list.push((
-                Ipv4Addr::new(b[i], b[i + 1], b[i + 2], b[i + 3]),
-                BigEndian::read_u32(&b[i + 4..i + 8]),
-            ));
-            i += 8;
-        }
+    /// The most destinations the 8-bit DestCount field can announce
+    pub const MAX_DESTS: usize = 255;
 
+    /// Return a RERR message from a byte slice
+    pub fn new(b: &[u8]) -> Result<RERR, ParseError> {
+        let v = RerrView::new(b)?;
         Ok(RERR {
-            n: 1 << 7 & b[1] != 0,
-            dest_count: udest_list.len() as u8,
-            udest_list,
+            n: v.n(),
+            udest_list: v.udests().collect(),
+            extensions: Extension::parse_all(v.extension_bytes())?,
         })
     }
     /// Return the bit field representation of a RERR message
     pub fn bit_message(&self) -> Vec<u8> {
-        let mut b = Vec::with_capacity(4 + 8 * self.dest_count as usize);
-        b.push(3);
-        b.push(if self.n { 1 << 7 } else { 0 });
-        b.push(0);
-        b.push(self.dest_count);
-
-        let mut buf = [0; 4];
-        for i in 0..self.udest_list.len() as usize {
-            // Add each ip address
-            b.extend(self.udest_list[i].0.octets().iter());
-
-            // Add its sequence number
-            BigEndian::write_u32(&mut buf, self.udest_list[i].1);
-            b.extend(buf.iter());
-        }
+        let mut b = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut b);
         b
     }
-    pub fn handle_message(&mut self, addr: &SocketAddr) {
-        unimplemented!();
+    /// Write the bit field representation of a RERR message to `buf`
+    pub fn encode_into(&self, buf: &mut impl BufMut) {
+        buf.put_u8(3);
+        buf.put_u8(if self.n { 1 << 7 } else { 0 });
+        buf.put_u8(0);
+        buf.put_u8(self.dest_count());
+        for &(ip, seq_num) in &self.udest_list {
+            buf.put_slice(&ip.octets());
+            buf.put_u32_be(seq_num);
+        }
+        extension::write_all(buf, &self.extensions);
+    }
+    /// Return how many bytes the RERR takes up on the wire
+    pub fn encoded_len(&self) -> usize {
+        4 + 8 * self.udest_list.len() + extension::encoded_len(&self.extensions)
+    }
+    /// Return a RERR reporting a single unreachable destination
+    pub fn for_dest(n: bool, dest: Ipv4Addr, seq_num: u32) -> RERR {
+        RERR {
+            n,
+            udest_list: vec![(dest, seq_num)],
+            extensions: Vec::new(),
+        }
+    }
+    /// Return a RERR reporting every destination in `udest_list`, which must hold between 1 and
+    /// `MAX_DESTS` of them
+    pub fn with_dests(n: bool, udest_list: Vec<(Ipv4Addr, u32)>) -> Result<RERR, BuildError> {
+        match udest_list.len() {
+            0 => Err(BuildError::Missing("unreachable destination")),
+            count if count > RERR::MAX_DESTS => Err(BuildError::TooManyDests(count)),
+            _ => Ok(RERR {
+                n,
+                udest_list,
+                extensions: Vec::new(),
+            }),
+        }
+    }
+    /// Return as many RERRs as it takes to report every destination in `udest_list` with no
+    /// more than `MAX_DESTS` destinations or `max_len` bytes in each.
+    ///
+    /// Each RERR reports at least one destination, however small `max_len` is.
+    pub fn split(n: bool, udest_list: &[(Ipv4Addr, u32)], max_len: usize) -> Vec<RERR> {
+        let per_rerr = (max_len.saturating_sub(4) / 8).clamp(1, RERR::MAX_DESTS);
+        udest_list
+            .chunks(per_rerr)
+            .map(|chunk| RERR {
+                n,
+                udest_list: chunk.to_vec(),
+                extensions: Vec::new(),
+            })
+            .collect()
+    }
+    /// Return the unreachable destinations and their sequence numbers
+    pub fn udests(&self) -> &[(Ipv4Addr, u32)] {
+        &self.udest_list
+    }
+    /// Return the DestCount field, which always matches the list of destinations
+    pub fn dest_count(&self) -> u8 {
+        self.udest_list.len() as u8
+    }
+    /// Update the routing table from a RERR received from `addr`
+    pub fn handle_message(&mut self, addr: &SocketAddr, node: &mut Node) {
+        if let Some(prev_hop) = sender(addr) {
+            node.handle_rerr(self, prev_hop);
+        }
     }
 }
 
@@ -379,6 +785,7 @@ mod test_encoding {
             dest_seq_num: 12,
             orig_ip: Ipv4Addr::new(192, 168, 10, 19),
             orig_seq_num: 63,
+            extensions: Vec::new(),
         };
 
         let bytes: &[u8] = &[
@@ -400,6 +807,7 @@ mod test_encoding {
             dest_seq_num: 12,
             orig_ip: Ipv4Addr::new(192, 168, 10, 19),
             lifetime: 32603,
+            extensions: Vec::new(),
         };
 
         let bytes: &[u8] = &[
@@ -412,29 +820,23 @@ mod test_encoding {
 
     #[test]
     fn test_rerr_encoding() {
-        let mut udest_list = Vec::with_capacity(3);
-        udest_list.push((Ipv4Addr::new(192, 168, 10, 18), 482755));
-        udest_list.push((Ipv4Addr::new(255, 255, 255, 255), 0));
-        let rerr = RERR {
-            n: false,
-            dest_count: 2,
-            udest_list: udest_list,
-        };
+        let udest_list = vec![
+            (Ipv4Addr::new(192, 168, 10, 18), 482755),
+            (Ipv4Addr::new(255, 255, 255, 255), 0),
+        ];
+        let rerr = RERR::with_dests(false, udest_list).unwrap();
         let bytes: &[u8] = &[
             3, 0, 0, 2, 192, 168, 10, 18, 0, 7, 93, 195, 255, 255, 255, 255, 0, 0, 0, 0,
         ];
         assert_eq!(bytes, rerr.bit_message().as_slice());
         assert_eq!(rerr, RERR::new(bytes).unwrap());
 
-        let mut udest_list = Vec::with_capacity(3);
-        udest_list.push((Ipv4Addr::new(192, 168, 10, 18), 482755));
-        udest_list.push((Ipv4Addr::new(255, 255, 255, 255), 0));
-        udest_list.push((Ipv4Addr::new(192, 168, 10, 15), 58392910));
-        let rerr = RERR {
-            n: false,
-            dest_count: 3,
-            udest_list: udest_list,
-        };
+        let udest_list = vec![
+            (Ipv4Addr::new(192, 168, 10, 18), 482755),
+            (Ipv4Addr::new(255, 255, 255, 255), 0),
+            (Ipv4Addr::new(192, 168, 10, 15), 58392910),
+        ];
+        let rerr = RERR::with_dests(false, udest_list).unwrap();
         let bytes: &[u8] = &[
             3, 0, 0, 3, 192, 168, 10, 18, 0, 7, 93, 195, 255, 255, 255, 255, 0, 0, 0, 0, 192, 168,
             10, 15, 3, 123, 1, 78,
@@ -443,4 +845,301 @@ mod test_encoding {
         assert_eq!(bytes, rerr.bit_message().as_slice());
         assert_eq!(rerr, RERR::new(bytes).unwrap());
     }
+
+    #[test]
+    fn test_hello_parsing() {
+        let bytes: &[u8] = &[
+            2, 0, 0, 0, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 14, 0, 0, 7, 208,
+        ];
+        match AodvMessage::parse(bytes).unwrap() {
+            AodvMessage::Hello(r) => assert_eq!(r.lifetime, 2000),
+            m => panic!("expected a Hello, got {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let rreq: &[u8] = &[
+            1, 168, 0, 144, 0, 0, 56, 89, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 19, 0, 0, 0,
+            63,
+        ];
+        assert_eq!(AodvMessage::parse(&[]), Err(ParseError::Empty));
+        assert_eq!(AodvMessage::parse(&[9, 0]), Err(ParseError::UnknownType(9)));
+        assert_eq!(
+            AodvMessage::parse(&rreq[..23]),
+            Err(ParseError::Truncated {
+                expected: 24,
+                got: 23
+            })
+        );
+        assert_eq!(
+            AodvMessage::parse(&[4, 0, 0]),
+            Err(ParseError::BadLength {
+                msg_type: 4,
+                len: 3
+            })
+        );
+        assert_eq!(
+            RREP::new(&rreq[..20]),
+            Err(ParseError::WrongType {
+                expected: 2,
+                got: 1
+            })
+        );
+        assert_eq!(
+            RERR::new(&[3, 0, 0, 2, 192, 168, 10, 18, 0, 7, 93, 195]),
+            Err(ParseError::DestCountMismatch { count: 2, found: 1 })
+        );
+    }
+
+    #[test]
+    fn test_aggregated_datagram() {
+        let mut b = vec![4, 0];
+        b.extend_from_slice(&[
+            1, 168, 0, 144, 0, 0, 56, 89, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 19, 0, 0, 0,
+            63,
+        ]);
+        b.extend_from_slice(&[3, 0, 0, 1, 192, 168, 10, 18, 0, 7, 93, 195]);
+
+        let msgs = AodvMessage::parse_all(&b).unwrap();
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(msgs[0], AodvMessage::Ack);
+        assert_eq!(msgs[1], AodvMessage::Rreq(RREQ::new(&b[2..26]).unwrap()));
+        // A message cut short looks like a broken extension of the one before it
+        assert_eq!(
+            AodvMessage::parse_all(&b[..b.len() - 1]),
+            Err(ParseError::BadExtension { kind: 168, len: 10 })
+        );
+        // and a trailing RREP-ACK reads the same as an empty extension of type 4
+        let mut rreq = b[2..26].to_vec();
+        rreq.extend_from_slice(&[4, 0]);
+        assert_eq!(AodvMessage::parse_all(&rreq).unwrap().len(), 1);
+
+        // The codec consumes one message at a time
+        let mut src = BytesMut::from(&b[..]);
+        let mut decoded = Vec::new();
+        while let Some(msg) = AodvCodec.decode_eof(&mut src).unwrap() {
+            decoded.push(msg);
+        }
+        assert_eq!(decoded, msgs);
+        assert!(src.is_empty());
+
+        // and drops the rest of a datagram it can't make sense of
+        let mut src = BytesMut::from(&b[2..26]);
+        src.extend_from_slice(&[3, 0, 0, 0, 9, 9]);
+        assert!(AodvCodec.decode_eof(&mut src).unwrap().is_some());
+        assert!(AodvCodec.decode_eof(&mut src).is_err());
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_batch() {
+        let rerr = AodvMessage::Rerr(RERR::for_dest(
+            false,
+            Ipv4Addr::new(192, 168, 10, 18),
+            482755,
+        ));
+        let msgs = vec![rerr.clone(), AodvMessage::Ack, rerr.clone(), rerr.clone()];
+
+        // A RREP-ACK after a RERR would read as an extension of it
+        let lens: Vec<usize> = batch(&msgs, 26).iter().map(|d| d.len()).collect();
+        assert_eq!(lens, vec![12, 26]);
+        assert_eq!(batch(&msgs, 0).len(), 4);
+        assert_eq!(decode_all(batch(&msgs, 1472)), msgs);
+
+        // A Hello's extensions would swallow a RERR packed after it
+        let mut hello = RREP::hello(Ipv4Addr::new(192, 168, 10, 14), 12, 2000);
+        hello.extensions.push(Extension::HelloInterval(1000));
+        let msgs = vec![AodvMessage::Hello(hello), rerr.clone(), rerr];
+        let lens: Vec<usize> = batch(&msgs, 1472).iter().map(|d| d.len()).collect();
+        assert_eq!(lens, vec![26, 24]);
+        assert_eq!(decode_all(batch(&msgs, 1472)), msgs);
+    }
+
+    /// Run every datagram through the codec as the server does
+    fn decode_all(datagrams: Vec<BytesMut>) -> Vec<AodvMessage> {
+        let mut msgs = Vec::new();
+        for mut src in datagrams {
+            while let Some(msg) = AodvCodec.decode_eof(&mut src).unwrap() {
+                msgs.push(msg);
+            }
+        }
+        msgs
+    }
+
+    #[test]
+    fn test_extensions() {
+        let mut b = vec![
+            2, 0, 0, 0, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 14, 0, 0, 7, 208,
+        ];
+        b.extend_from_slice(&[1, 4, 0, 0, 3, 232, 200, 2, 9, 9]);
+        let hello = match AodvMessage::parse(&b).unwrap() {
+            AodvMessage::Hello(r) => r,
+            m => panic!("expected a Hello, got {:?}", m),
+        };
+        assert_eq!(hello.hello_interval(), Some(1000));
+        assert_eq!(
+            hello.extensions[1],
+            Extension::Unknown {
+                kind: 200,
+                data: vec![9, 9]
+            }
+        );
+        // Unknown extensions survive a round trip untouched
+        assert_eq!(hello.bit_message(), b);
+
+        // even an empty one that reads the same as a RREP-ACK
+        let mut rrep = RREP::hello(Ipv4Addr::new(192, 168, 10, 14), 12, 2000);
+        rrep.extensions.push(Extension::Unknown {
+            kind: 4,
+            data: Vec::new(),
+        });
+        let msgs = AodvMessage::parse_all(&rrep.bit_message()).unwrap();
+        assert_eq!(msgs, vec![AodvMessage::Hello(rrep)]);
+
+        // Only the last message of a datagram can have extensions
+        let mut b = vec![4, 0];
+        b.extend_from_slice(&[3, 0, 0, 1, 192, 168, 10, 18, 0, 7, 93, 195, 200, 0]);
+        let msgs = AodvMessage::parse_all(&b).unwrap();
+        assert_eq!(msgs.len(), 2);
+        match msgs[1] {
+            AodvMessage::Rerr(ref r) => assert_eq!(r.extensions.len(), 1),
+            ref m => panic!("expected a RERR, got {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let mut b = vec![
+            2, 0, 0, 0, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 14, 0, 0, 7, 208,
+        ];
+        b.extend_from_slice(&[1, 4, 0, 0, 3, 232]);
+        let hello = AodvMessage::parse(&b).unwrap();
+        assert_eq!(hello.encoded_len(), b.len());
+
+        // The codec writes straight into the frame without an intermediate Vec
+        let mut buf = BytesMut::new();
+        AodvCodec.encode(hello, &mut buf).unwrap();
+        AodvCodec.encode(AodvMessage::Ack, &mut buf).unwrap();
+        assert_eq!(&buf[..b.len()], &b[..]);
+        assert_eq!(&buf[b.len()..], &[4, 0]);
+    }
+
+    #[test]
+    fn test_rerr_split() {
+        let udest_list: Vec<(Ipv4Addr, u32)> = (0..600u32)
+            .map(|i| (Ipv4Addr::from(0xc0a8_0000 + i), i))
+            .collect();
+
+        // The count byte caps a RERR at 255 destinations
+        let rerrs = RERR::split(true, &udest_list, 65_535);
+        let counts: Vec<u8> = rerrs.iter().map(RERR::dest_count).collect();
+        assert_eq!(counts, vec![255, 255, 90]);
+        assert!(rerrs.iter().all(|r| r.n));
+        let rejoined: Vec<_> = rerrs.iter().flat_map(|r| r.udests().to_vec()).collect();
+        assert_eq!(rejoined, udest_list);
+
+        // and the MTU can cap it further
+        let rerrs = RERR::split(false, &udest_list, 1500 - HEADER_LEN);
+        assert_eq!(rerrs[0].dest_count(), 183);
+        assert!(rerrs.iter().all(|r| r.encoded_len() <= 1500 - HEADER_LEN));
+        let b = rerrs[0].bit_message();
+        assert_eq!(b[3], 183);
+        assert_eq!(RERR::new(&b).unwrap(), rerrs[0]);
+
+        assert_eq!(RERR::split(false, &udest_list[..3], 0).len(), 3);
+        assert!(RERR::split(false, &[], 1472).is_empty());
+
+        // A single RERR can't report nothing, nor more than its count byte holds
+        assert_eq!(
+            RERR::with_dests(false, Vec::new()),
+            Err(BuildError::Missing("unreachable destination"))
+        );
+        assert_eq!(
+            RERR::with_dests(false, udest_list[..256].to_vec()),
+            Err(BuildError::TooManyDests(256))
+        );
+        let rerr = RERR::with_dests(true, udest_list[..255].to_vec()).unwrap();
+        assert_eq!(rerr, RERR::split(true, &udest_list[..255], 65_535)[0]);
+    }
+
+    #[test]
+    fn test_hello_ttl() {
+        let bytes: &[u8] = &[
+            2, 0, 0, 0, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 14, 0, 0, 7, 208,
+        ];
+        let hello = AodvMessage::parse_with_ttl(bytes, 1).unwrap();
+        assert_eq!(hello.kind(), MessageKind::Hello);
+        assert_eq!(AodvMessage::parse(bytes).unwrap(), hello);
+
+        // A RREP shaped like a Hello that travelled further than a hop isn't one
+        let rrep = AodvMessage::parse_with_ttl(bytes, 2).unwrap();
+        assert_eq!(rrep.kind(), MessageKind::Rrep);
+        assert_eq!(rrep.bit_message(), hello.bit_message());
+
+        let mut b = vec![4, 0];
+        b.extend_from_slice(bytes);
+        let kinds: Vec<MessageKind> = AodvMessage::parse_all_with_ttl(&b, 5)
+            .unwrap()
+            .iter()
+            .map(AodvMessage::kind)
+            .collect();
+        assert_eq!(kinds, vec![MessageKind::Ack, MessageKind::Rrep]);
+        assert_eq!(MessageKind::Ack.to_string(), "RREP-ACK");
+    }
+
+    #[test]
+    #[should_panic(expected = "RREP prefix size 32 doesn't fit in 5 bits")]
+    fn test_prefix_size_overflow() {
+        let mut rrep = RREP::hello(Ipv4Addr::new(10, 0, 0, 1), 1, 2000);
+        rrep.prefix_size = 32;
+        rrep.bit_message();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use serde_json;
+
+        let msg: AodvMessage =
+            "RREQ id=14425 flags=J,G,U hops=144 dest=192.168.10.14/12 orig=192.168.10.19/63"
+                .parse()
+                .unwrap();
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["type"], "Rreq");
+        assert_eq!(json["join"], true);
+        assert_eq!(json["repair"], false);
+        assert_eq!(json["dest_ip"], "192.168.10.14");
+        assert_eq!(serde_json::from_value::<AodvMessage>(json).unwrap(), msg);
+
+        let rerr = AodvMessage::Rerr(RERR::for_dest(true, Ipv4Addr::new(10, 0, 0, 2), 7));
+        let json = serde_json::to_string(&rerr).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"Rerr","no_delete":true,"udests":[{"ip":"10.0.0.2","seq_num":7}],"extensions":[]}"#
+        );
+        assert_eq!(serde_json::from_str::<AodvMessage>(&json).unwrap(), rerr);
+        // A RERR has to report something
+        let empty = r#"{"type":"Rerr","no_delete":true,"udests":[],"extensions":[]}"#;
+        assert!(serde_json::from_str::<AodvMessage>(empty).is_err());
+
+        // Nor may a RREP's prefix size overflow its 5 bits
+        let rrep = RREP::builder()
+            .dest(Ipv4Addr::new(10, 0, 0, 2), 9)
+            .orig(Ipv4Addr::new(10, 0, 0, 1))
+            .prefix_size(31)
+            .build()
+            .unwrap();
+        let mut json = serde_json::to_value(AodvMessage::Rrep(rrep)).unwrap();
+        assert_eq!(json["prefix_size"], 31);
+        let rrep = serde_json::from_value::<AodvMessage>(json.clone()).unwrap();
+        assert_eq!(rrep.bit_message()[2], 31);
+        json["prefix_size"] = 32.into();
+        assert!(serde_json::from_value::<AodvMessage>(json).is_err());
+
+        assert_eq!(
+            serde_json::to_string(&AodvMessage::Ack).unwrap(),
+            r#"{"type":"Ack"}"#
+        );
+    }
 }
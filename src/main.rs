@@ -5,12 +5,33 @@ extern crate aodv;
 use std::env::var;
 use std::process::exit;
 
-use aodv::{config, server};
+use aodv::node::Node;
+use aodv::pcap::{self, PcapReader};
+use aodv::{config, decode, server};
 
 fn main() {
     // Get command line arguments
     let args = config::get_args();
 
+    // Take apart the bytes of a message
+    if let Some(args) = args.subcommand_matches("decode") {
+        let bytes = match decode::input(args.value_of("hex"), args.value_of("file")) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Unable to read message bytes: {}", e);
+                exit(2);
+            }
+        };
+        match decode::dissect(&bytes) {
+            Ok(text) => print!("{}", text),
+            Err(malformed) => {
+                print!("{}", malformed);
+                exit(1);
+            }
+        }
+        return;
+    }
+
     // Start server
     if args.is_present("start_aodv") {
         // Check user is root
@@ -21,11 +42,20 @@ fn main() {
                     exit(1);
                 }
             }
-            Err(e) => panic!(e),
+            Err(e) => panic!("{}", e),
         }
 
         // Start internal server
-        server::aodv();
+        server::aodv(config::Config::new(&args));
+    } else if let Some(path) = args.value_of("replay") {
+        let mut node = Node::new(config::Config::new(&args));
+        match PcapReader::open(path).and_then(|r| pcap::replay(r, &mut node, true)) {
+            Ok(handled) => println!("Replayed {} messages from {}", handled, path),
+            Err(e) => {
+                eprintln!("Unable to replay {}: {}", path, e);
+                exit(1);
+            }
+        }
     } else {
         println!("{}", args.usage());
     }
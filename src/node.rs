@@ -0,0 +1,1708 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Duration, Utc};
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use super::buffer::{self, PacketQueue};
+use super::config::Config;
+use super::discovery::{Discovery, Repair};
+use super::ratelimit::RateLimiter;
+use super::routing::{RouteEntry, RouteState, RouteTable};
+use super::seq::{OwnSeqNum, SeqNum};
+use super::state::SavedState;
+use super::{AodvMessage, Extension, HEADER_LEN, RERR, RREP, RREQ};
+
+/// A control message waiting to be sent by the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outgoing {
+    pub dest: Ipv4Addr,   // Next hop or broadcast address
+    pub msg: AodvMessage, // Message to send
+    pub ttl: Option<u32>, // IP TTL, or the socket default if `None`
+}
+
+/// The state of a single aodv router that message handlers act upon
+#[derive(Debug)]
+pub struct Node {
+    pub config: Config,
+    pub routes: RouteTable,
+    pub outbox: Vec<Outgoing>,
+    // Buffered data packets whose route came back, with the next hop to send them to. Whoever
+    // owns the data path has to drain these; at most `buffer_max_packets` are kept.
+    pub ready: Vec<(Ipv4Addr, Vec<u8>)>,
+    // ICMP Destination Unreachable messages for packets no route was found for, capped the same
+    pub unreachable: Vec<Vec<u8>>,
+
+    // Limits on the RREQs and RERRs we originate
+    pub rreq_limiter: RateLimiter<Outgoing>,
+    pub rerr_limiter: RateLimiter<Outgoing>,
+
+    pub seq_num: OwnSeqNum, // Our own sequence number
+    pub rreq_id: u32,       // ID of the last RREQ we originated
+
+    // Neighbours we have heard Hellos from and when to consider them lost
+    neighbours: HashMap<Ipv4Addr, DateTime<Utc>>,
+    // When we last broadcast a control message
+    last_broadcast: DateTime<Utc>,
+    // When we may send RREPs again after a reboot
+    quiet_until: DateTime<Utc>,
+    // Next hops we sent a RREP with the A flag to and when to stop waiting for the RREP-ACK
+    pending_acks: HashMap<Ipv4Addr, DateTime<Utc>>,
+    // Neighbours with unidirectional links and when to trust them again
+    blacklist: HashMap<Ipv4Addr, DateTime<Utc>>,
+
+    // (Originator IP, RREQ ID) of recently seen RREQs and when to forget them
+    rreq_cache: HashMap<(Ipv4Addr, u32), DateTime<Utc>>,
+    // Route discoveries waiting for a RREP
+    discoveries: HashMap<Ipv4Addr, Discovery>,
+    // Local repairs waiting for a RREP
+    repairs: HashMap<Ipv4Addr, Repair>,
+    // Data packets waiting for a route, by destination
+    buffered: HashMap<Ipv4Addr, PacketQueue>,
+    // Data packets thrown away by full queues so far
+    dropped_packets: usize,
+}
+
+impl Node {
+    pub fn new(config: Config) -> Self {
+        Node {
+            routes: RouteTable::new(&config),
+            outbox: Vec::new(),
+            ready: Vec::new(),
+            unreachable: Vec::new(),
+            rreq_limiter: RateLimiter::new(config.RREQ_RATELIMIT, config.rate_limit_policy),
+            rerr_limiter: RateLimiter::new(config.RERR_RATELIMIT, config.rate_limit_policy),
+            seq_num: OwnSeqNum::default(),
+            rreq_id: 0,
+            neighbours: HashMap::new(),
+            last_broadcast: Utc::now(),
+            quiet_until: Utc::now(),
+            pending_acks: HashMap::new(),
+            blacklist: HashMap::new(),
+            rreq_cache: HashMap::new(),
+            discoveries: HashMap::new(),
+            repairs: HashMap::new(),
+            buffered: HashMap::new(),
+            dropped_packets: 0,
+            config,
+        }
+    }
+    /// Queue a message for the server to send
+    pub fn send(&mut self, dest: Ipv4Addr, msg: AodvMessage, ttl: Option<u32>) {
+        self.enqueue(Outgoing { dest, msg, ttl });
+    }
+    /// Queue a message to be broadcast to every neighbour
+    pub fn broadcast(&mut self, msg: AodvMessage, ttl: Option<u32>) {
+        let dest = self.config.broadcast_address;
+        self.send(dest, msg, ttl);
+    }
+    /// Queue a RREQ or RERR we originate, subject to RREQ_RATELIMIT or RERR_RATELIMIT
+    fn send_limited(&mut self, out: Outgoing) {
+        let allowed = match out.msg {
+            AodvMessage::Rreq(_) => self.rreq_limiter.offer(out),
+            _ => self.rerr_limiter.offer(out),
+        };
+        if let Some(out) = allowed {
+            self.enqueue(out);
+        }
+    }
+    fn enqueue(&mut self, out: Outgoing) {
+        if out.dest == self.config.broadcast_address {
+            self.last_broadcast = Utc::now();
+        }
+        self.outbox.push(out);
+    }
+    /// Stay quiet for DELETE_PERIOD after a reboot as per section 6.13 of the RFC.
+    ///
+    /// Until then no RREPs or Hellos are sent, since neighbours may still have routes to us with
+    /// sequence numbers we have forgotten.
+    pub fn start_quiet_period(&mut self) {
+        self.quiet_until = Utc::now() + self.config.DELETE_PERIOD;
+    }
+    /// Return whether we are still in the quiet period after a reboot
+    pub fn quiet(&self) -> bool {
+        self.quiet_until > Utc::now()
+    }
+    /// Return what should survive a restart of the daemon
+    pub fn saved_state(&self) -> SavedState {
+        SavedState {
+            saved: Utc::now(),
+            seq_num: self.seq_num.get(),
+            rreq_id: self.rreq_id,
+            blacklist: self
+                .blacklist
+                .iter()
+                .filter(|&(ip, _)| self.blacklisted(ip))
+                .map(|(ip, expiry)| (*ip, *expiry))
+                .collect(),
+            routes: self
+                .routes
+                .iter()
+                .filter(|r| r.is_valid())
+                .cloned()
+                .collect(),
+        }
+    }
+    /// Pick up where a previous run left off, keeping only what hasn't expired since.
+    ///
+    /// Start the quiet period of section 6.13 of the RFC unless the state was saved less than
+    /// DELETE_PERIOD ago, and return whether it was.
+    pub fn restore(&mut self, state: SavedState) -> bool {
+        let now = Utc::now();
+        self.seq_num = OwnSeqNum::new(state.seq_num);
+        self.rreq_id = state.rreq_id;
+        for (ip, expiry) in state.blacklist {
+            if expiry > now {
+                self.blacklist.insert(ip, expiry);
+            }
+        }
+        for route in state.routes {
+            if route.lifetime > now {
+                self.routes.update(route);
+            }
+        }
+        let recent = now - state.saved < self.config.DELETE_PERIOD;
+        if !recent {
+            self.start_quiet_period();
+        }
+        recent
+    }
+    /// Unicast a RREP, asking for a RREP-ACK if the link to the next hop may be unidirectional
+    pub fn send_rrep(&mut self, next_hop: Ipv4Addr, mut rrep: RREP) {
+        if self.quiet() {
+            return;
+        }
+        rrep.a = self.suspect_link(&next_hop);
+        if rrep.a {
+            let deadline = Utc::now() + self.config.NEXT_HOP_WAIT;
+            self.pending_acks.insert(next_hop, deadline);
+        }
+        self.send(next_hop, AodvMessage::Rrep(rrep), None);
+    }
+    /// Process a RREP-ACK confirming the link to `neighbour` works both ways
+    pub fn handle_ack(&mut self, neighbour: Ipv4Addr) {
+        self.pending_acks.remove(&neighbour);
+    }
+    /// Return whether the link to `neighbour` may only work one way as per section 6.8 of the
+    /// RFC: other neighbours' Hellos reach us but its own don't. Without Hellos there is nothing
+    /// to go on, so every link is trusted.
+    fn suspect_link(&self, neighbour: &Ipv4Addr) -> bool {
+        !self.neighbours.is_empty() && !self.neighbours.contains_key(neighbour)
+    }
+    /// Return whether `neighbour` is blacklisted as per section 6.8 of the RFC
+    pub fn blacklisted(&self, neighbour: &Ipv4Addr) -> bool {
+        match self.blacklist.get(neighbour) {
+            Some(expiry) => *expiry > Utc::now(),
+            None => false,
+        }
+    }
+    /// Blacklist next hops that never acknowledged their RREP for BLACKLIST_TIMEOUT
+    fn check_acks(&mut self) {
+        let now = Utc::now();
+        let unacked: Vec<Ipv4Addr> = self
+            .pending_acks
+            .iter()
+            .filter(|&(_, deadline)| *deadline <= now)
+            .map(|(ip, _)| *ip)
+            .collect();
+        for neighbour in unacked {
+            self.pending_acks.remove(&neighbour);
+            self.blacklist
+                .insert(neighbour, now + self.config.BLACKLIST_TIMEOUT);
+            self.link_break(neighbour);
+        }
+        self.blacklist.retain(|_, expiry| *expiry > now);
+    }
+    /// Remember a RREQ for PATH_DISCOVERY_TIME, returning whether it was already seen
+    pub fn seen_rreq(&mut self, orig_ip: Ipv4Addr, rreq_id: u32) -> bool {
+        let now = Utc::now();
+        match self.rreq_cache.get(&(orig_ip, rreq_id)) {
+            Some(expiry) if *expiry > now => return true,
+            _ => {}
+        }
+        let expiry = now + self.config.PATH_DISCOVERY_TIME;
+        self.rreq_cache.insert((orig_ip, rreq_id), expiry);
+        false
+    }
+    /// Return the next hop towards `dest`, or start a route discovery if there isn't one
+    pub fn request_route(&mut self, dest: Ipv4Addr) -> Option<Ipv4Addr> {
+        if let Some(route) = self.routes.get_valid(&dest) {
+            return Some(route.next_hop);
+        }
+        if !self.discoveries.contains_key(&dest) {
+            let last_hop_count = self.routes.get(&dest).map(|r| r.hop_count);
+            let discovery = Discovery::new(dest, last_hop_count, &self.config);
+            self.send_rreq(dest, discovery.ttl);
+            self.discoveries.insert(dest, discovery);
+        }
+        None
+    }
+    /// Return whether a route discovery for `dest` is in progress
+    pub fn discovering(&self, dest: &Ipv4Addr) -> bool {
+        self.discoveries.contains_key(dest)
+    }
+    /// Originate a RREQ for `dest` as per section 6.3 of the RFC
+    fn send_rreq(&mut self, dest: Ipv4Addr, ttl: usize) {
+        let orig_seq_num = self.seq_num.before_rreq();
+        self.rreq_id = self.rreq_id.wrapping_add(1);
+
+        let dest_seq_num = match self.routes.get(&dest) {
+            Some(r) if r.valid_seq_num => Some(r.dest_seq_num.into()),
+            _ => None,
+        };
+        let mut rreq = RREQ::builder()
+            .dest(dest)
+            .orig(self.config.current_ip, orig_seq_num.into())
+            .rreq_id(self.rreq_id);
+        if let Some(dest_seq_num) = dest_seq_num {
+            rreq = rreq.dest_seq_num(dest_seq_num);
+        }
+        let rreq = rreq.build().expect("RREQ has a destination and originator");
+        self.seen_rreq(rreq.orig_ip, rreq.rreq_id);
+        self.send_limited(Outgoing {
+            dest: self.config.broadcast_address,
+            msg: AodvMessage::Rreq(rreq),
+            ttl: Some(ttl as u32),
+        });
+    }
+    /// Resend or give up on every route discovery whose RREQ went unanswered
+    fn retry_discoveries(&mut self) {
+        let mut expired = Vec::new();
+        for (dest, discovery) in &self.discoveries {
+            if self.routes.get_valid(dest).is_some() || discovery.expired() {
+                expired.push(*dest);
+            }
+        }
+        for dest in expired {
+            let mut discovery = self.discoveries.remove(&dest).unwrap();
+            if let Some(next_hop) = self.routes.get_valid(&dest).map(|r| r.next_hop) {
+                for packet in self.take_buffered(&dest) {
+                    self.release(next_hop, packet);
+                }
+                continue;
+            }
+            if discovery.next_attempt(&self.config) {
+                self.send_rreq(dest, discovery.ttl);
+                self.discoveries.insert(dest, discovery);
+            } else {
+                println!("Route discovery for {} failed", dest);
+                self.give_up(&dest);
+            }
+        }
+    }
+    /// Create or refresh the one hop route to a neighbour we just heard from
+    pub fn update_neighbour(&mut self, neighbour: Ipv4Addr) {
+        let route = RouteEntry {
+            dest_ip: neighbour,
+            dest_seq_num: SeqNum::default(),
+            valid_seq_num: false,
+            state: RouteState::Valid,
+            interface: self.config.interface.clone(),
+            hop_count: 1,
+            next_hop: neighbour,
+            precursors: Vec::new(),
+            lifetime: Utc::now() + self.config.ACTIVE_ROUTE_TIMEOUT,
+        };
+        if !self.routes.update(route) {
+            self.routes.refresh(&neighbour);
+        }
+        // Any message counts as a sign of life, not just Hellos
+        let deadline = Utc::now() + self.hello_lifetime();
+        if let Some(d) = self.neighbours.get_mut(&neighbour) {
+            if deadline > *d {
+                *d = deadline;
+            }
+        }
+    }
+    /// How long a neighbour may stay silent before its link is considered lost
+    fn hello_lifetime(&self) -> Duration {
+        self.config.HELLO_INTERVAL * self.config.ALLOWED_HELLO_LOSS as i32
+    }
+    /// Process a Hello from a neighbour as per section 6.9 of the RFC
+    pub fn handle_hello(&mut self, hello: &RREP, neighbour: Ipv4Addr) {
+        if neighbour == self.config.current_ip {
+            return;
+        }
+        let lifetime = Duration::milliseconds(i64::from(hello.lifetime));
+        self.routes.update(RouteEntry {
+            dest_ip: neighbour,
+            dest_seq_num: hello.dest_seq_num.into(),
+            valid_seq_num: true,
+            state: RouteState::Valid,
+            interface: self.config.interface.clone(),
+            hop_count: 1,
+            next_hop: neighbour,
+            precursors: Vec::new(),
+            lifetime: Utc::now() + lifetime,
+        });
+        // The Hello's sequence number and the one hop route are taken as is, even if the
+        // sequence number looks older than ours
+        if let Some(route) = self.routes.get_mut(&neighbour) {
+            if route.state == RouteState::Valid {
+                route.extend_lifetime(lifetime);
+            } else {
+                route.lifetime = Utc::now() + lifetime;
+            }
+            route.dest_seq_num = hello.dest_seq_num.into();
+            route.valid_seq_num = true;
+            route.state = RouteState::Valid;
+            route.hop_count = 1;
+            route.next_hop = neighbour;
+        }
+        // Lose the neighbour after ALLOWED_HELLO_LOSS of its own Hello intervals if it said
+        let lost_after = match hello.hello_interval() {
+            Some(interval) => {
+                Duration::milliseconds(i64::from(interval)) * self.config.ALLOWED_HELLO_LOSS as i32
+            }
+            None => lifetime,
+        };
+        self.neighbours.insert(neighbour, Utc::now() + lost_after);
+    }
+    /// Broadcast a Hello if we are part of an active route and have been quiet for
+    /// HELLO_INTERVAL
+    fn send_hello(&mut self) {
+        if self.quiet() || self.last_broadcast + self.config.HELLO_INTERVAL > Utc::now() {
+            return;
+        }
+        let active = self
+            .routes
+            .iter()
+            .any(|r| r.is_valid() && (r.next_hop != r.dest_ip || !r.precursors.is_empty()));
+        if !active {
+            return;
+        }
+        let mut hello = RREP::hello(
+            self.config.current_ip,
+            self.seq_num.get().into(),
+            self.hello_lifetime().num_milliseconds() as u32,
+        );
+        hello.extensions.push(Extension::HelloInterval(
+            self.config.HELLO_INTERVAL.num_milliseconds() as u32,
+        ));
+        self.broadcast(AodvMessage::Hello(hello), Some(1));
+    }
+    /// Declare the links to neighbours whose Hellos stopped arriving lost
+    fn detect_lost_neighbours(&mut self) {
+        let now = Utc::now();
+        let lost: Vec<Ipv4Addr> = self
+            .neighbours
+            .iter()
+            .filter(|&(_, deadline)| *deadline <= now)
+            .map(|(ip, _)| *ip)
+            .collect();
+        for neighbour in lost {
+            self.neighbours.remove(&neighbour);
+            self.link_break(neighbour);
+        }
+    }
+    /// Process a RREQ that arrived with IP TTL `ttl` as per section 6.5 of the RFC
+    pub fn handle_rreq(&mut self, rreq: &mut RREQ, prev_hop: Ipv4Addr, ttl: u8) {
+        if self.blacklisted(&prev_hop) || prev_hop == self.config.current_ip {
+            return;
+        }
+        // Silently discard our own and duplicate RREQs before they can touch any route
+        if rreq.orig_ip == self.config.current_ip || self.seen_rreq(rreq.orig_ip, rreq.rreq_id) {
+            return;
+        }
+        self.update_neighbour(prev_hop);
+
+        rreq.hop_count = rreq.hop_count.saturating_add(1);
+        self.update_reverse_route(rreq, prev_hop);
+
+        if rreq.dest_ip == self.config.current_ip {
+            self.reply_as_destination(rreq, prev_hop);
+            return;
+        }
+        if !rreq.d && !self.quiet() && self.reply_as_intermediate(rreq, prev_hop) {
+            return;
+        }
+
+        // We can't answer, so pass the request on with the freshest sequence number we know, as
+        // long as it has hops left to go. Right after a reboot we might pass on a stale one.
+        if ttl <= 1 || self.quiet() {
+            return;
+        }
+        if let Some(route) = self.routes.get(&rreq.dest_ip) {
+            let known = SeqNum::from(rreq.dest_seq_num);
+            if route.valid_seq_num && (rreq.u || route.dest_seq_num > known) {
+                rreq.dest_seq_num = route.dest_seq_num.into();
+                rreq.u = false;
+            }
+        }
+        self.broadcast(AodvMessage::Rreq(rreq.clone()), Some(u32::from(ttl - 1)));
+    }
+    /// Answer a RREQ for our own address as per section 6.6.1 of the RFC
+    fn reply_as_destination(&mut self, rreq: &RREQ, prev_hop: Ipv4Addr) {
+        if self.quiet() {
+            return;
+        }
+        let requested = if rreq.u {
+            None
+        } else {
+            Some(SeqNum::from(rreq.dest_seq_num))
+        };
+        let dest_seq_num = self.seq_num.before_rrep(requested);
+
+        let rrep = RREP::reply_to(
+            rreq,
+            dest_seq_num.into(),
+            0,
+            self.config.MY_ROUTE_TIMEOUT.num_milliseconds() as u32,
+        );
+        self.send_rrep(prev_hop, rrep);
+    }
+    /// Answer a RREQ from a fresh enough route as per sections 6.6.2 and 6.6.3 of the RFC,
+    /// returning whether we could
+    fn reply_as_intermediate(&mut self, rreq: &RREQ, prev_hop: Ipv4Addr) -> bool {
+        let now = Utc::now();
+        let (forward, reverse) = match (
+            self.routes.get_valid(&rreq.dest_ip),
+            self.routes.get_valid(&rreq.orig_ip),
+        ) {
+            (Some(f), Some(r)) => (f.clone(), r.clone()),
+            _ => return false,
+        };
+        if !forward.valid_seq_num || !rreq.u && forward.dest_seq_num < rreq.dest_seq_num.into() {
+            return false;
+        }
+
+        // Both ends of the route now go through us
+        if let Some(r) = self.routes.get_mut(&rreq.dest_ip) {
+            r.add_precursor(prev_hop);
+        }
+        if let Some(r) = self.routes.get_mut(&rreq.orig_ip) {
+            r.add_precursor(forward.next_hop);
+        }
+
+        let rrep = RREP::reply_to(
+            rreq,
+            forward.dest_seq_num.into(),
+            forward.hop_count,
+            (forward.lifetime - now).num_milliseconds() as u32,
+        );
+        self.send_rrep(prev_hop, rrep);
+
+        // Tell the destination how to reach the originator too
+        if rreq.g {
+            let gratuitous = RREP {
+                r: false,
+                a: false,
+                prefix_size: 0,
+                hop_count: reverse.hop_count,
+                dest_ip: rreq.orig_ip,
+                dest_seq_num: rreq.orig_seq_num,
+                orig_ip: rreq.dest_ip,
+                lifetime: (reverse.lifetime - now).num_milliseconds() as u32,
+                extensions: Vec::new(),
+            };
+            self.send_rrep(forward.next_hop, gratuitous);
+        }
+        true
+    }
+    /// Create or update the route back to the originator of a RREQ
+    fn update_reverse_route(&mut self, rreq: &RREQ, prev_hop: Ipv4Addr) {
+        // Minimal lifetime as per section 6.5 of the RFC
+        let lifetime = Utc::now() + self.config.NET_TRAVERSAL_TIME * 2
+            - self.config.NODE_TRAVERSAL_TIME * 2 * i32::from(rreq.hop_count);
+
+        if let Some(route) = self.routes.get_mut(&rreq.orig_ip) {
+            let orig_seq_num = SeqNum::from(rreq.orig_seq_num);
+            if !route.valid_seq_num || orig_seq_num > route.dest_seq_num {
+                route.dest_seq_num = orig_seq_num;
+            }
+            route.valid_seq_num = true;
+            route.state = RouteState::Valid;
+            route.next_hop = prev_hop;
+            route.hop_count = rreq.hop_count;
+            if lifetime > route.lifetime {
+                route.lifetime = lifetime;
+            }
+            return;
+        }
+        self.routes.update(RouteEntry {
+            dest_ip: rreq.orig_ip,
+            dest_seq_num: rreq.orig_seq_num.into(),
+            valid_seq_num: true,
+            state: RouteState::Valid,
+            interface: self.config.interface.clone(),
+            hop_count: rreq.hop_count,
+            next_hop: prev_hop,
+            precursors: Vec::new(),
+            lifetime,
+        });
+    }
+    /// Record the forward route to the destination of a RREP
+    pub fn handle_rrep(&mut self, rrep: &mut RREP, prev_hop: Ipv4Addr) {
+        if prev_hop == self.config.current_ip {
+            return;
+        }
+        self.update_neighbour(prev_hop);
+        if rrep.a {
+            self.send(prev_hop, AodvMessage::Ack, Some(1));
+        }
+        if rrep.dest_ip == self.config.current_ip {
+            return;
+        }
+
+        rrep.hop_count = rrep.hop_count.saturating_add(1);
+        let updated = self.routes.update(RouteEntry {
+            dest_ip: rrep.dest_ip,
+            dest_seq_num: rrep.dest_seq_num.into(),
+            valid_seq_num: true,
+            state: RouteState::Valid,
+            interface: self.config.interface.clone(),
+            hop_count: rrep.hop_count,
+            next_hop: prev_hop,
+            precursors: Vec::new(),
+            lifetime: Utc::now() + Duration::milliseconds(i64::from(rrep.lifetime)),
+        });
+
+        // Only a RREP that created or improved the forward route is worth passing on
+        if !updated || rrep.orig_ip == self.config.current_ip {
+            return;
+        }
+        self.forward_rrep(rrep, prev_hop);
+    }
+    /// Send a RREP on towards its originator along the reverse route as per section 6.7 of the RFC
+    fn forward_rrep(&mut self, rrep: &RREP, prev_hop: Ipv4Addr) {
+        if self.quiet() {
+            self.quiet_rerr(rrep.orig_ip);
+            return;
+        }
+        let next_hop = match self.routes.get_valid(&rrep.orig_ip) {
+            Some(r) => r.next_hop,
+            None => return,
+        };
+        if let Some(r) = self.routes.get_mut(&rrep.dest_ip) {
+            r.add_precursor(next_hop);
+        }
+        if let Some(r) = self.routes.get_mut(&prev_hop) {
+            r.add_precursor(next_hop);
+        }
+        if let Some(r) = self.routes.get_mut(&rrep.orig_ip) {
+            r.add_precursor(prev_hop);
+        }
+        self.routes.refresh(&rrep.orig_ip);
+        self.send_rrep(next_hop, rrep.clone());
+    }
+    /// Invalidate every route the sender of a RERR reported as unreachable through it,
+    /// and pass the news on to our own precursors as per section 6.11 of the RFC.
+    ///
+    /// A RERR with the N flag set comes from a node repairing the route locally, so the routes
+    /// are kept but the RERR is still passed on.
+    pub fn handle_rerr(&mut self, rerr: &RERR, prev_hop: Ipv4Addr) {
+        let mut unreachable = Vec::new();
+        let mut precursors = Vec::new();
+        for &(ip, seq) in rerr.udests() {
+            let route = match self.routes.get(&ip) {
+                Some(r) if r.state != RouteState::Invalid && r.next_hop == prev_hop => r.clone(),
+                _ => continue,
+            };
+            if !rerr.n {
+                self.routes.invalidate(&ip);
+                if let Some(r) = self.routes.get_mut(&ip) {
+                    r.dest_seq_num = seq.into();
+                }
+            }
+            if !route.precursors.is_empty() {
+                unreachable.push((ip, seq));
+                add_all(&mut precursors, &route.precursors);
+            }
+        }
+        self.send_rerr(rerr.n, unreachable, &precursors);
+    }
+    /// Invalidate every route through a neighbour we can no longer reach and tell the
+    /// precursors of those routes with a RERR.
+    ///
+    /// Lost Hellos and missing RREP-ACKs are noticed here; whoever sends data calls this when a
+    /// packet can't be delivered to the next hop.
+    pub fn link_break(&mut self, neighbour: Ipv4Addr) {
+        let mut unreachable = Vec::new();
+        let mut precursors = Vec::new();
+        for dest in self.routes.routes_via(&neighbour) {
+            let hops_to_source = self.hops_to_source(&dest);
+            if self.start_repair(dest, hops_to_source) {
+                continue;
+            }
+            if let Some(route) = self.routes.invalidate(&dest) {
+                if !route.precursors.is_empty() {
+                    unreachable.push((dest, route.dest_seq_num.into()));
+                    add_all(&mut precursors, &route.precursors);
+                }
+            }
+        }
+        self.send_rerr(false, unreachable, &precursors);
+    }
+    /// Return how many hops away the sources of the data we forward to `dest` are, taking the
+    /// longest valid route back through one of its precursors
+    fn hops_to_source(&self, dest: &Ipv4Addr) -> u8 {
+        let precursors = match self.routes.get(dest) {
+            Some(r) => r.precursors.clone(),
+            None => return 0,
+        };
+        precursors
+            .iter()
+            .flat_map(|p| self.routes.routes_via(p))
+            .filter_map(|d| self.routes.get_valid(&d).map(|r| r.hop_count))
+            .max()
+            .unwrap_or(0)
+    }
+    /// Try to repair a broken route we forward data on as per section 6.12 of the RFC,
+    /// returning whether a repair was started
+    fn start_repair(&mut self, dest: Ipv4Addr, hops_to_source: u8) -> bool {
+        let repair = match self.routes.get_mut(&dest) {
+            Some(r) if !r.precursors.is_empty() && Repair::possible(r.hop_count, &self.config) => {
+                r.state = RouteState::Repairing;
+                if r.valid_seq_num {
+                    r.dest_seq_num = r.dest_seq_num.next();
+                }
+                r.lifetime = Utc::now() + self.config.DELETE_PERIOD;
+                Repair::new(dest, r.hop_count, hops_to_source, &self.config)
+            }
+            _ => return false,
+        };
+        self.send_rreq(dest, repair.ttl);
+        self.repairs.insert(dest, repair);
+        true
+    }
+    /// Finish every local repair that was answered or timed out
+    fn check_repairs(&mut self) {
+        let done: Vec<Ipv4Addr> = self
+            .repairs
+            .values()
+            .filter(|r| r.expired() || self.routes.get_valid(&r.dest_ip).is_some())
+            .map(|r| r.dest_ip)
+            .collect();
+        for dest in done {
+            let repair = self.repairs.remove(&dest).unwrap();
+            let route = match self.routes.get_valid(&dest) {
+                Some(r) => r.clone(),
+                None => {
+                    println!("Local repair of the route to {} failed", dest);
+                    self.give_up(&dest);
+                    // Fall back to telling everyone upstream the route is gone
+                    if let Some(route) = self.routes.invalidate(&dest).cloned() {
+                        let unreachable = vec![(dest, route.dest_seq_num.into())];
+                        self.send_rerr(false, unreachable, &route.precursors);
+                    }
+                    continue;
+                }
+            };
+            for packet in self.take_buffered(&dest) {
+                self.release(route.next_hop, packet);
+            }
+            // Let the source know it may want to look for a shorter route
+            if route.hop_count > repair.hop_count {
+                let unreachable = vec![(dest, route.dest_seq_num.into())];
+                self.send_rerr(true, unreachable, &route.precursors);
+            }
+        }
+    }
+    /// Route a packet from a local application, holding it while a route is found as per
+    /// section 6.3 of the RFC, and return the next hop if it can be sent now
+    pub fn send_data(&mut self, dest: Ipv4Addr, packet: Vec<u8>) -> Option<Ipv4Addr> {
+        let repairing = self
+            .routes
+            .get(&dest)
+            .is_some_and(|r| r.state == RouteState::Repairing);
+        if !repairing {
+            if let Some(next_hop) = self.request_route(dest) {
+                self.routes.refresh(&dest);
+                self.routes.refresh(&next_hop);
+                return Some(next_hop);
+            }
+        }
+        self.buffer(dest, packet);
+        None
+    }
+    /// Hold a packet until the route to `dest` is found or repaired
+    fn buffer(&mut self, dest: Ipv4Addr, packet: Vec<u8>) {
+        let config = &self.config;
+        self.buffered
+            .entry(dest)
+            .or_insert_with(|| {
+                PacketQueue::new(
+                    config.buffer_max_bytes,
+                    config.buffer_max_packets,
+                    config.buffer_drop_policy,
+                )
+            })
+            .push(packet);
+    }
+    /// Remove and return the packets held for `dest`, keeping count of the ones its queue dropped
+    fn take_buffered(&mut self, dest: &Ipv4Addr) -> Vec<Vec<u8>> {
+        match self.buffered.remove(dest) {
+            Some(mut q) => {
+                self.dropped_packets += q.dropped();
+                q.drain()
+            }
+            None => Vec::new(),
+        }
+    }
+    /// Hand a buffered packet over to be sent to `next_hop`, dropping the oldest one waiting if
+    /// nobody has collected them
+    fn release(&mut self, next_hop: Ipv4Addr, packet: Vec<u8>) {
+        let max = self.config.buffer_max_packets;
+        self.dropped_packets += push_capped(&mut self.ready, (next_hop, packet), max);
+    }
+    /// Drop the packets held for `dest` now that no route was found, answering each IPv4 one with
+    /// an ICMP Destination Unreachable
+    fn give_up(&mut self, dest: &Ipv4Addr) {
+        let own_ip = self.config.current_ip;
+        let max = self.config.buffer_max_packets;
+        for packet in self.take_buffered(dest) {
+            self.dropped_packets += 1;
+            if let Some(icmp) = buffer::destination_unreachable(own_ip, &packet) {
+                self.dropped_packets += push_capped(&mut self.unreachable, icmp, max);
+            }
+        }
+    }
+    /// Return how many data packets were dropped so far, by full queues, for want of a route or
+    /// for want of being collected from `ready` and `unreachable`. Queues still waiting for a
+    /// route aren't counted.
+    pub fn dropped_packets(&self) -> usize {
+        self.dropped_packets
+    }
+    /// Return the next hop for a data packet from `prev_hop` to `dest`.
+    ///
+    /// Packets for a route under repair are buffered until the repair finishes, and the
+    /// previous hop is sent a RERR if there is no route at all. Right after a reboot every
+    /// packet is answered with a broadcast RERR instead.
+    pub fn forward_data(
+        &mut self,
+        prev_hop: Ipv4Addr,
+        dest: Ipv4Addr,
+        packet: Vec<u8>,
+    ) -> Option<Ipv4Addr> {
+        if self.quiet() {
+            // Only data keeps a rebooted node waiting
+            self.quiet_rerr(dest);
+            self.start_quiet_period();
+            return None;
+        }
+        if let Some(next_hop) = self.routes.get_valid(&dest).map(|r| r.next_hop) {
+            self.routes.refresh(&dest);
+            self.routes.refresh(&next_hop);
+            return Some(next_hop);
+        }
+        match self.routes.get(&dest) {
+            Some(r) if r.state == RouteState::Repairing => {
+                self.buffer(dest, packet);
+            }
+            r => {
+                let seq = r.map_or(0, |r| r.dest_seq_num.into());
+                self.send_rerr(false, vec![(dest, seq)], &[prev_hop]);
+            }
+        }
+        None
+    }
+    /// Answer traffic for `dest` that would need a route right after a reboot with a broadcast
+    /// RERR as per section 6.13 of the RFC
+    fn quiet_rerr(&mut self, dest: Ipv4Addr) {
+        let seq = self.routes.get(&dest).map_or(0, |r| r.dest_seq_num.into());
+        self.send_limited(Outgoing {
+            dest: self.config.broadcast_address,
+            msg: AodvMessage::Rerr(RERR::for_dest(false, dest, seq)),
+            ttl: Some(1),
+        });
+    }
+    /// Send a RERR by unicast if there is a single precursor and by broadcast otherwise
+    fn send_rerr(&mut self, n: bool, udest_list: Vec<(Ipv4Addr, u32)>, precursors: &[Ipv4Addr]) {
+        if udest_list.is_empty() || precursors.is_empty() {
+            return;
+        }
+        let dest = if precursors.len() == 1 {
+            precursors[0]
+        } else {
+            self.config.broadcast_address
+        };
+        let max_len = self.config.mtu.saturating_sub(HEADER_LEN);
+        for rerr in RERR::split(n, &udest_list, max_len) {
+            self.send_limited(Outgoing {
+                dest,
+                msg: AodvMessage::Rerr(rerr),
+                ttl: Some(1),
+            });
+        }
+    }
+    /// Do any periodic work that depends on the passage of time
+    pub fn tick(&mut self) {
+        let now = Utc::now();
+        self.detect_lost_neighbours();
+        self.routes.purge();
+        self.rreq_cache.retain(|_, expiry| *expiry > now);
+        for out in self.rreq_limiter.release() {
+            self.enqueue(out);
+        }
+        for out in self.rerr_limiter.release() {
+            self.enqueue(out);
+        }
+        self.check_acks();
+        self.retry_discoveries();
+        self.check_repairs();
+        self.send_hello();
+    }
+}
+
+/// Add `item` to the end of `list`, dropping from the front to keep at most `max` items, and
+/// return how many were dropped
+fn push_capped<T>(list: &mut Vec<T>, item: T, max: usize) -> usize {
+    list.push(item);
+    let excess = list.len().saturating_sub(max);
+    list.drain(..excess);
+    excess
+}
+
+/// Add every address in `ips` to `list` that isn't already in it
+fn add_all(list: &mut Vec<Ipv4Addr>, ips: &[Ipv4Addr]) {
+    for ip in ips {
+        if !list.contains(ip) {
+            list.push(*ip);
+        }
+    }
+}
+
+/// Return the IPv4 address a message came from, if it came over IPv4
+pub fn sender(addr: &SocketAddr) -> Option<Ipv4Addr> {
+    match addr.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test_node {
+    use super::*;
+    use ratelimit::RateLimitPolicy;
+
+    /// IP TTL the RREQs in these tests arrive with
+    const TTL: u8 = 64;
+
+    fn node(ip: Ipv4Addr) -> Node {
+        Node::new(Config {
+            current_ip: ip,
+            ..Config::default()
+        })
+    }
+
+    fn rreq() -> RREQ {
+        RREQ::builder()
+            .dest(Ipv4Addr::new(192, 168, 10, 14))
+            .orig(Ipv4Addr::new(192, 168, 10, 19), 63)
+            .rreq_id(7)
+            .hop_count(2)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rreq_forwarding() {
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+
+        let unknown = Extension::Unknown {
+            kind: 200,
+            data: vec![1, 2],
+        };
+        let mut r = rreq();
+        r.extensions.push(unknown.clone());
+        node.handle_rreq(&mut r, prev_hop, TTL);
+
+        // Reverse route to the originator through the previous hop
+        let route = node
+            .routes
+            .get_valid(&Ipv4Addr::new(192, 168, 10, 19))
+            .unwrap();
+        assert_eq!(
+            (route.next_hop, route.hop_count, route.dest_seq_num),
+            (prev_hop, 3, SeqNum(63))
+        );
+        assert!(node.routes.get_valid(&prev_hop).is_some());
+
+        // Rebroadcast one hop further along
+        assert_eq!(node.outbox.len(), 1);
+        let out = node.outbox.pop().unwrap();
+        assert_eq!(out.dest, node.config.broadcast_address);
+        assert_eq!(out.ttl, Some(u32::from(TTL - 1)));
+        match out.msg {
+            AodvMessage::Rreq(r) => assert_eq!((r.hop_count, r.extensions), (3, vec![unknown])),
+            m => panic!("expected a RREQ, got {:?}", m),
+        }
+
+        // The same request again is a duplicate, and leaves no trace
+        let other = Ipv4Addr::new(192, 168, 10, 3);
+        node.handle_rreq(&mut rreq(), other, TTL);
+        assert!(node.outbox.is_empty());
+        assert!(node.routes.get(&other).is_none());
+    }
+
+    #[test]
+    fn test_own_messages_ignored() {
+        let own_ip = Ipv4Addr::new(192, 168, 10, 19);
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        let mut node = node(own_ip);
+
+        // Our own RREQ coming back, directly or through a neighbour
+        node.handle_rreq(&mut rreq(), own_ip, TTL);
+        node.handle_rreq(&mut rreq(), prev_hop, TTL);
+        let mut r = RREQ {
+            orig_ip: prev_hop,
+            ..rreq()
+        };
+        node.handle_rreq(&mut r, own_ip, TTL);
+        let mut rrep = RREP::reply_to(&rreq(), 9, 0, 6000);
+        node.handle_rrep(&mut rrep, own_ip);
+
+        assert!(node.routes.is_empty());
+        assert!(node.outbox.is_empty());
+    }
+
+    #[test]
+    fn test_rreq_ttl() {
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+
+        // The edge of an expanding ring search goes no further, but still leaves a route back
+        node.handle_rreq(&mut rreq(), prev_hop, 1);
+        assert!(node.outbox.is_empty());
+        assert!(node
+            .routes
+            .get_valid(&Ipv4Addr::new(192, 168, 10, 19))
+            .is_some());
+
+        let mut r = rreq();
+        r.rreq_id += 1;
+        node.handle_rreq(&mut r, prev_hop, 3);
+        assert_eq!(node.outbox.pop().unwrap().ttl, Some(2));
+    }
+
+    #[test]
+    fn test_request_route() {
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 19));
+
+        assert_eq!(node.request_route(dest), None);
+        assert!(node.discovering(&dest));
+        // Only one RREQ until the ring times out
+        assert_eq!(node.request_route(dest), None);
+        assert_eq!(node.outbox.len(), 1);
+
+        let out = node.outbox.pop().unwrap();
+        assert_eq!(out.ttl, Some(node.config.TTL_START as u32));
+        match out.msg {
+            AodvMessage::Rreq(r) => {
+                assert_eq!(
+                    (r.orig_ip, r.orig_seq_num, r.rreq_id),
+                    (node.config.current_ip, 1, 1)
+                );
+                assert!(r.u);
+            }
+            m => panic!("expected a RREQ, got {:?}", m),
+        }
+
+        // The answer ends the discovery
+        node.update_neighbour(dest);
+        node.tick();
+        assert!(!node.discovering(&dest));
+        assert_eq!(node.request_route(dest), Some(dest));
+    }
+
+    #[test]
+    fn test_rreq_for_us_is_not_forwarded() {
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 14));
+        node.handle_rreq(&mut rreq(), Ipv4Addr::new(192, 168, 10, 2), TTL);
+        assert!(node
+            .outbox
+            .iter()
+            .all(|o| o.msg != AodvMessage::Rreq(rreq())));
+        assert!(node
+            .routes
+            .get_valid(&Ipv4Addr::new(192, 168, 10, 19))
+            .is_some());
+    }
+
+    #[test]
+    fn test_destination_reply() {
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 14));
+        node.seq_num = OwnSeqNum::new(SeqNum(4));
+        node.handle_rreq(&mut rreq(), prev_hop, TTL);
+
+        assert_eq!(node.outbox.len(), 1);
+        let out = node.outbox.pop().unwrap();
+        assert_eq!(out.dest, prev_hop);
+        match out.msg {
+            AodvMessage::Rrep(r) => {
+                assert_eq!(
+                    (r.dest_ip, r.orig_ip),
+                    (node.config.current_ip, rreq().orig_ip)
+                );
+                assert_eq!((r.hop_count, r.dest_seq_num), (0, 5));
+                assert_eq!(
+                    i64::from(r.lifetime),
+                    node.config.MY_ROUTE_TIMEOUT.num_milliseconds()
+                );
+            }
+            m => panic!("expected a RREP, got {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_intermediate_and_gratuitous_reply() {
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        let towards_dest = Ipv4Addr::new(192, 168, 10, 8);
+        let dest = rreq().dest_ip;
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        node.routes.update(RouteEntry {
+            dest_ip: dest,
+            dest_seq_num: SeqNum(12),
+            valid_seq_num: true,
+            state: RouteState::Valid,
+            interface: String::from("wlan0"),
+            hop_count: 2,
+            next_hop: towards_dest,
+            precursors: Vec::new(),
+            lifetime: Utc::now() + Duration::milliseconds(3000),
+        });
+
+        // The destination only flag forces a rebroadcast
+        let mut r = rreq();
+        r.d = true;
+        node.handle_rreq(&mut r, prev_hop, TTL);
+        assert_eq!(
+            node.outbox.pop().unwrap().dest,
+            node.config.broadcast_address
+        );
+
+        let mut r = rreq();
+        r.rreq_id += 1;
+        r.g = true;
+        node.handle_rreq(&mut r, prev_hop, TTL);
+        assert_eq!(node.outbox.len(), 2);
+        let (reply, gratuitous) = match (&node.outbox[0].msg, &node.outbox[1].msg) {
+            (AodvMessage::Rrep(a), AodvMessage::Rrep(b)) => (a.clone(), b.clone()),
+            m => panic!("expected two RREPs, got {:?}", m),
+        };
+        assert_eq!(node.outbox[0].dest, prev_hop);
+        assert_eq!(
+            (reply.dest_ip, reply.hop_count, reply.dest_seq_num),
+            (dest, 2, 12)
+        );
+        assert_eq!(node.outbox[1].dest, towards_dest);
+        assert_eq!((gratuitous.dest_ip, gratuitous.hop_count), (r.orig_ip, 3));
+
+        assert_eq!(node.routes.get(&dest).unwrap().precursors, vec![prev_hop]);
+        assert_eq!(
+            node.routes.get(&r.orig_ip).unwrap().precursors,
+            vec![towards_dest]
+        );
+    }
+
+    #[test]
+    fn test_rrep_forwarding() {
+        let orig_side = Ipv4Addr::new(192, 168, 10, 2);
+        let dest_side = Ipv4Addr::new(192, 168, 10, 8);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        node.handle_rreq(&mut rreq(), orig_side, TTL);
+        node.outbox.clear();
+
+        let mut rrep = RREP {
+            r: false,
+            a: false,
+            prefix_size: 0,
+            hop_count: 1,
+            dest_ip: rreq().dest_ip,
+            dest_seq_num: 9,
+            orig_ip: rreq().orig_ip,
+            lifetime: 6000,
+            extensions: Vec::new(),
+        };
+        node.handle_rrep(&mut rrep, dest_side);
+
+        let route = node.routes.get_valid(&rreq().dest_ip).unwrap();
+        assert_eq!((route.next_hop, route.hop_count), (dest_side, 2));
+        assert_eq!(route.precursors, vec![orig_side]);
+        assert_eq!(
+            node.outbox,
+            vec![Outgoing {
+                dest: orig_side,
+                msg: AodvMessage::Rrep(RREP {
+                    hop_count: 2,
+                    ..rrep.clone()
+                }),
+                ttl: None,
+            }]
+        );
+
+        // A staler reply along another path changes nothing, so it goes no further
+        node.outbox.clear();
+        let mut stale = RREP {
+            dest_seq_num: 8,
+            hop_count: 0,
+            ..rrep
+        };
+        node.handle_rrep(&mut stale, Ipv4Addr::new(192, 168, 10, 9));
+        assert_eq!(
+            node.routes.get_valid(&rreq().dest_ip).unwrap().next_hop,
+            dest_side
+        );
+        assert!(node.outbox.is_empty());
+    }
+
+    fn route_via(dest: Ipv4Addr, next_hop: Ipv4Addr, precursors: Vec<Ipv4Addr>) -> RouteEntry {
+        RouteEntry {
+            dest_ip: dest,
+            dest_seq_num: SeqNum(20),
+            valid_seq_num: true,
+            state: RouteState::Valid,
+            interface: String::from("wlan0"),
+            hop_count: 3,
+            next_hop,
+            precursors,
+            lifetime: Utc::now() + Duration::milliseconds(3000),
+        }
+    }
+
+    #[test]
+    fn test_idle_route_expiry() {
+        let neighbour = Ipv4Addr::new(192, 168, 10, 8);
+        let upstream = Ipv4Addr::new(192, 168, 10, 2);
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        node.update_neighbour(neighbour);
+        node.routes
+            .update(route_via(dest, neighbour, vec![upstream]));
+        node.routes.get_mut(&neighbour).unwrap().lifetime = Utc::now();
+
+        // A route nobody used just expires, it isn't a broken link
+        node.tick();
+        assert_eq!(
+            node.routes.get(&neighbour).unwrap().state,
+            RouteState::Invalid
+        );
+        assert_eq!(node.routes.get(&dest).unwrap().state, RouteState::Valid);
+        assert!(node.outbox.is_empty());
+    }
+
+    #[test]
+    fn test_link_break() {
+        let broken = Ipv4Addr::new(192, 168, 10, 8);
+        let upstream = Ipv4Addr::new(192, 168, 10, 2);
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        // Too far away to repair locally
+        node.routes.update(RouteEntry {
+            hop_count: 12,
+            ..route_via(dest, broken, vec![upstream])
+        });
+        node.routes
+            .update(route_via(Ipv4Addr::new(192, 168, 10, 15), broken, vec![]));
+
+        node.link_break(broken);
+
+        assert!(node.routes.routes_via(&broken).is_empty());
+        // Only routes someone else depends on are reported
+        assert_eq!(
+            node.outbox,
+            vec![Outgoing {
+                dest: upstream,
+                msg: AodvMessage::Rerr(RERR::for_dest(false, dest, 21)),
+                ttl: Some(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_link_break_split() {
+        let broken = Ipv4Addr::new(192, 168, 10, 8);
+        let upstream = Ipv4Addr::new(192, 168, 10, 2);
+        // Room for two destinations per RERR
+        let mut node = Node::new(Config {
+            current_ip: Ipv4Addr::new(192, 168, 10, 5),
+            mtu: HEADER_LEN + 20,
+            ..Config::default()
+        });
+        for i in 0..5 {
+            let dest = Ipv4Addr::new(192, 168, 11, i);
+            node.routes.update(RouteEntry {
+                hop_count: 12,
+                ..route_via(dest, broken, vec![upstream])
+            });
+        }
+
+        node.link_break(broken);
+
+        let mut reported = Vec::new();
+        for out in &node.outbox {
+            match out.msg {
+                AodvMessage::Rerr(ref r) => {
+                    assert!(r.dest_count() <= 2);
+                    reported.extend(r.udests().iter().map(|&(ip, _)| ip));
+                }
+                ref m => panic!("expected a RERR, got {:?}", m),
+            }
+        }
+        assert_eq!(node.outbox.len(), 3);
+        reported.sort();
+        assert_eq!(
+            reported,
+            (0..5)
+                .map(|i| Ipv4Addr::new(192, 168, 11, i))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_rerr_propagation() {
+        let downstream = Ipv4Addr::new(192, 168, 10, 8);
+        let precursors = vec![
+            Ipv4Addr::new(192, 168, 10, 2),
+            Ipv4Addr::new(192, 168, 10, 3),
+        ];
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        node.routes.update(route_via(dest, downstream, precursors));
+
+        let mut rerr = RERR::for_dest(true, dest, 25);
+
+        // Locally repaired routes are kept
+        node.handle_rerr(&rerr, downstream);
+        assert!(node.routes.get_valid(&dest).is_some());
+        assert_eq!(
+            node.outbox.pop().unwrap().dest,
+            node.config.broadcast_address
+        );
+
+        // RERRs from other neighbours don't affect the route
+        rerr.n = false;
+        node.handle_rerr(&rerr, Ipv4Addr::new(192, 168, 10, 9));
+        assert!(node.routes.get_valid(&dest).is_some());
+        assert!(node.outbox.is_empty());
+
+        node.handle_rerr(&rerr, downstream);
+        let route = node.routes.get(&dest).unwrap();
+        assert_eq!(
+            (route.state, route.dest_seq_num),
+            (RouteState::Invalid, SeqNum(25))
+        );
+        assert_eq!(
+            node.outbox.pop().unwrap().msg,
+            AodvMessage::Rerr(rerr.clone())
+        );
+    }
+
+    #[test]
+    fn test_hello() {
+        let neighbour = Ipv4Addr::new(192, 168, 10, 8);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        let hello = RREP {
+            r: false,
+            a: false,
+            prefix_size: 0,
+            hop_count: 0,
+            dest_ip: neighbour,
+            dest_seq_num: 3,
+            orig_ip: neighbour,
+            lifetime: 2000,
+            extensions: Vec::new(),
+        };
+        node.handle_hello(&hello, neighbour);
+        let route = node.routes.get_valid(&neighbour).unwrap();
+        assert_eq!((route.hop_count, route.dest_seq_num), (1, SeqNum(3)));
+
+        // A Hello's sequence number replaces ours even if it looks stale
+        node.handle_hello(
+            &RREP {
+                dest_seq_num: 2,
+                ..hello.clone()
+            },
+            neighbour,
+        );
+        assert_eq!(node.routes.get(&neighbour).unwrap().dest_seq_num, SeqNum(2));
+        node.routes.invalidate(&neighbour);
+        node.handle_hello(
+            &RREP {
+                dest_seq_num: 1,
+                ..hello.clone()
+            },
+            neighbour,
+        );
+        let route = node.routes.get_valid(&neighbour).unwrap();
+        assert_eq!((route.hop_count, route.dest_seq_num), (1, SeqNum(1)));
+
+        // Our own Hello coming back doesn't make us our own neighbour
+        let own_ip = node.config.current_ip;
+        node.handle_hello(&RREP::hello(own_ip, 1, 2000), own_ip);
+        assert!(node.routes.get(&own_ip).is_none());
+        assert!(!node.neighbours.contains_key(&own_ip));
+
+        // Neighbour routes alone don't make us send Hellos
+        node.last_broadcast = Utc::now() - node.config.HELLO_INTERVAL;
+        node.tick();
+        assert!(node.outbox.is_empty());
+
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        node.routes.update(route_via(dest, neighbour, vec![]));
+        node.tick();
+        let out = node.outbox.pop().unwrap();
+        assert_eq!(
+            (out.dest, out.ttl),
+            (node.config.broadcast_address, Some(1))
+        );
+        match out.msg {
+            AodvMessage::Hello(h) => {
+                assert_eq!(
+                    (h.dest_ip, h.orig_ip),
+                    (node.config.current_ip, node.config.current_ip)
+                );
+                assert_eq!(h.lifetime, 2000);
+                assert_eq!(h.hello_interval(), Some(1000));
+            }
+            m => panic!("expected a Hello, got {:?}", m),
+        }
+
+        // A neighbour saying it sends Hellos less often is given longer
+        let slow = RREP {
+            extensions: vec![Extension::HelloInterval(5000)],
+            ..hello
+        };
+        node.handle_hello(&slow, neighbour);
+        assert!(node.neighbours[&neighbour] > Utc::now() + Duration::milliseconds(9000));
+
+        // Silence past the allowed loss breaks the link
+        *node.neighbours.get_mut(&neighbour).unwrap() = Utc::now();
+        node.tick();
+        assert!(node.routes.get_valid(&dest).is_none());
+        assert!(node.routes.get_valid(&neighbour).is_none());
+    }
+
+    #[test]
+    fn test_rrep_ack_and_blacklist() {
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        let other = Ipv4Addr::new(192, 168, 10, 3);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 14));
+
+        // Without Hellos there's no reason to doubt the link
+        node.handle_rreq(&mut rreq(), prev_hop, TTL);
+        match node.outbox.pop().unwrap().msg {
+            AodvMessage::Rrep(r) => assert!(!r.a),
+            m => panic!("expected a RREP, got {:?}", m),
+        }
+
+        // Another neighbour's Hellos reach us but the previous hop's don't, so ask for an
+        // acknowledgement
+        node.handle_hello(&RREP::hello(other, 1, 3000), other);
+        let mut r = rreq();
+        r.rreq_id += 1;
+        node.handle_rreq(&mut r, prev_hop, TTL);
+        match node.outbox.pop().unwrap().msg {
+            AodvMessage::Rrep(r) => assert!(r.a),
+            m => panic!("expected a RREP, got {:?}", m),
+        }
+        node.handle_ack(prev_hop);
+        node.tick();
+        assert!(!node.blacklisted(&prev_hop));
+
+        // No acknowledgement this time
+        r.rreq_id += 1;
+        node.handle_rreq(&mut r, prev_hop, TTL);
+        *node.pending_acks.get_mut(&prev_hop).unwrap() = Utc::now();
+        node.tick();
+        assert!(node.blacklisted(&prev_hop));
+        assert!(node.routes.get_valid(&rreq().orig_ip).is_none());
+
+        node.outbox.clear();
+        r.rreq_id += 1;
+        node.handle_rreq(&mut r, prev_hop, TTL);
+        assert!(node.outbox.is_empty());
+    }
+
+    #[test]
+    fn test_rrep_ack_reply() {
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 19));
+        let mut rrep = RREP {
+            r: false,
+            a: true,
+            prefix_size: 0,
+            hop_count: 0,
+            dest_ip: rreq().dest_ip,
+            dest_seq_num: 9,
+            orig_ip: rreq().orig_ip,
+            lifetime: 6000,
+            extensions: Vec::new(),
+        };
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        node.handle_rrep(&mut rrep, prev_hop);
+        assert_eq!(
+            node.outbox,
+            vec![Outgoing {
+                dest: prev_hop,
+                msg: AodvMessage::Ack,
+                ttl: Some(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_local_repair() {
+        let broken = Ipv4Addr::new(192, 168, 10, 8);
+        let upstream = Ipv4Addr::new(192, 168, 10, 2);
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        node.routes.update(route_via(dest, broken, vec![upstream]));
+
+        node.link_break(broken);
+
+        // A RREQ instead of a RERR
+        assert_eq!(node.routes.get(&dest).unwrap().state, RouteState::Repairing);
+        let out = node.outbox.pop().unwrap();
+        assert_eq!(out.ttl, Some(5));
+        match out.msg {
+            AodvMessage::Rreq(r) => assert_eq!((r.dest_ip, r.dest_seq_num, r.u), (dest, 21, false)),
+            m => panic!("expected a RREQ, got {:?}", m),
+        }
+
+        // Data waits for the repair
+        assert_eq!(node.forward_data(upstream, dest, vec![1, 2, 3]), None);
+        assert!(node.outbox.is_empty());
+
+        // Repaired with a longer route
+        let other = Ipv4Addr::new(192, 168, 10, 9);
+        node.handle_rrep(
+            &mut RREP {
+                r: false,
+                a: false,
+                prefix_size: 0,
+                hop_count: 4,
+                dest_ip: dest,
+                dest_seq_num: 21,
+                orig_ip: node.config.current_ip,
+                lifetime: 6000,
+                extensions: Vec::new(),
+            },
+            other,
+        );
+        node.tick();
+        assert_eq!(node.ready, vec![(other, vec![1, 2, 3])]);
+        assert_eq!(
+            node.outbox.pop().unwrap().msg,
+            AodvMessage::Rerr(RERR::for_dest(true, dest, 21))
+        );
+        assert_eq!(node.forward_data(upstream, dest, vec![4]), Some(other));
+    }
+
+    #[test]
+    fn test_repair_ttl() {
+        let broken = Ipv4Addr::new(192, 168, 10, 8);
+        let upstream = Ipv4Addr::new(192, 168, 10, 2);
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        node.routes.update(route_via(dest, broken, vec![upstream]));
+        // The source of the data is 10 hops back through the precursor
+        let mut source = route_via(Ipv4Addr::new(192, 168, 10, 30), upstream, Vec::new());
+        source.hop_count = 10;
+        node.routes.update(source);
+
+        node.link_break(broken);
+
+        // max(MIN_REPAIR_TTL, 0.5 * #hops) + LOCAL_ADD_TTL
+        assert_eq!(node.outbox.pop().unwrap().ttl, Some(5 + 2));
+    }
+
+    #[test]
+    fn test_failed_local_repair() {
+        let broken = Ipv4Addr::new(192, 168, 10, 8);
+        let upstream = Ipv4Addr::new(192, 168, 10, 2);
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 5));
+        node.routes.update(route_via(dest, broken, vec![upstream]));
+
+        node.link_break(broken);
+        node.forward_data(upstream, dest, vec![1, 2, 3]);
+        node.outbox.clear();
+        node.repairs.get_mut(&dest).unwrap().deadline = Utc::now();
+        node.tick();
+
+        assert!(node.ready.is_empty());
+        assert_eq!(node.dropped_packets(), 1);
+        assert_eq!(node.routes.get(&dest).unwrap().state, RouteState::Invalid);
+        assert_eq!(
+            node.outbox.pop().unwrap(),
+            Outgoing {
+                dest: upstream,
+                msg: AodvMessage::Rerr(RERR::for_dest(false, dest, 21)),
+                ttl: Some(1),
+            }
+        );
+
+        // Without a route at all the previous hop hears about it straight away
+        assert_eq!(node.forward_data(upstream, dest, vec![4]), None);
+        assert_eq!(node.outbox.pop().unwrap().dest, upstream);
+    }
+
+    #[test]
+    fn test_rreq_rate_limit() {
+        let mut node = node(Ipv4Addr::new(192, 168, 10, 19));
+        let limit = node.config.RREQ_RATELIMIT;
+        for i in 0..limit + 1 {
+            node.request_route(Ipv4Addr::new(10, 0, 0, i as u8));
+        }
+        assert_eq!(node.outbox.len(), limit);
+        assert_eq!(node.rreq_limiter.queued(), 1);
+
+        let mut node = Node::new(Config {
+            rate_limit_policy: RateLimitPolicy::Drop,
+            ..Config::default()
+        });
+        for i in 0..limit + 1 {
+            node.request_route(Ipv4Addr::new(10, 0, 0, i as u8));
+        }
+        assert_eq!(node.outbox.len(), limit);
+        assert_eq!(node.rreq_limiter.dropped(), 1);
+    }
+
+    #[test]
+    fn test_quiet_after_reboot() {
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut node = node(dest);
+        node.start_quiet_period();
+
+        // Routes are still learnt, but no RREP goes out
+        node.handle_rreq(&mut rreq(), prev_hop, TTL);
+        assert!(node.outbox.is_empty());
+        assert!(node.routes.get_valid(&rreq().orig_ip).is_some());
+
+        // Data is answered with a RERR and pushes the quiet period back
+        node.quiet_until = Utc::now() + Duration::milliseconds(10);
+        assert_eq!(node.forward_data(prev_hop, rreq().orig_ip, vec![1]), None);
+        assert!(node.quiet_until > Utc::now() + Duration::milliseconds(10));
+        let out = node.outbox.pop().unwrap();
+        assert_eq!(out.dest, node.config.broadcast_address);
+        assert_eq!(
+            out.msg,
+            AodvMessage::Rerr(RERR::for_dest(false, rreq().orig_ip, 63))
+        );
+
+        // So is a RREP we would have to pass on, but that doesn't keep us waiting
+        node.outbox.clear();
+        let quiet_until = Utc::now() + Duration::milliseconds(10);
+        node.quiet_until = quiet_until;
+        let mut rrep = RREP {
+            r: false,
+            a: false,
+            prefix_size: 0,
+            hop_count: 1,
+            dest_ip: Ipv4Addr::new(192, 168, 10, 20),
+            dest_seq_num: 9,
+            orig_ip: rreq().orig_ip,
+            lifetime: 6000,
+            extensions: Vec::new(),
+        };
+        node.handle_rrep(&mut rrep, prev_hop);
+        assert_eq!(
+            node.outbox.pop().unwrap().msg,
+            AodvMessage::Rerr(RERR::for_dest(false, rreq().orig_ip, 63))
+        );
+        assert!(node.outbox.is_empty());
+        assert_eq!(node.quiet_until, quiet_until);
+
+        // Nor is a RREQ for someone else passed on
+        let mut r = RREQ {
+            dest_ip: Ipv4Addr::new(192, 168, 10, 30),
+            rreq_id: 8,
+            ..rreq()
+        };
+        node.handle_rreq(&mut r, prev_hop, TTL);
+        assert!(node.outbox.is_empty());
+
+        node.quiet_until = Utc::now();
+        let mut r = rreq();
+        r.rreq_id += 2;
+        node.handle_rreq(&mut r, prev_hop, TTL);
+        assert_eq!(node.outbox.len(), 1);
+    }
+
+    #[test]
+    fn test_restore_state() {
+        let prev_hop = Ipv4Addr::new(192, 168, 10, 2);
+        let mut old = node(Ipv4Addr::new(192, 168, 10, 14));
+        old.seq_num = OwnSeqNum::new(SeqNum(41));
+        old.rreq_id = 9;
+        old.handle_rreq(&mut rreq(), prev_hop, TTL);
+        old.blacklist
+            .insert(prev_hop, Utc::now() + Duration::milliseconds(5000));
+
+        let mut state = old.saved_state();
+        assert_eq!(state.routes.len(), 2);
+        let mut new = node(Ipv4Addr::new(192, 168, 10, 14));
+        assert!(new.restore(state.clone()));
+        assert!(!new.quiet());
+        assert_eq!((new.seq_num.get(), new.rreq_id), (SeqNum(42), 9));
+        assert!(new.blacklisted(&prev_hop));
+        assert!(new.routes.get_valid(&rreq().orig_ip).is_some());
+
+        // Too old to trust, and the routes have expired since
+        state.saved = Utc::now() - new.config.DELETE_PERIOD;
+        for route in &mut state.routes {
+            route.lifetime = Utc::now();
+        }
+        let mut new = node(Ipv4Addr::new(192, 168, 10, 14));
+        assert!(!new.restore(state));
+        assert!(new.quiet());
+        assert_eq!(new.seq_num.get(), SeqNum(42));
+        assert!(new.routes.is_empty());
+    }
+
+    #[test]
+    fn test_buffer_while_discovering() {
+        let own_ip = Ipv4Addr::new(192, 168, 10, 19);
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut packet = vec![0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0];
+        packet.extend_from_slice(&own_ip.octets());
+        packet.extend_from_slice(&dest.octets());
+
+        // Found
+        let mut found = node(own_ip);
+        assert_eq!(found.send_data(dest, packet.clone()), None);
+        assert_eq!(found.send_data(dest, vec![1]), None);
+        assert_eq!(found.outbox.len(), 1);
+        found.update_neighbour(dest);
+        found.tick();
+        assert_eq!(found.ready, vec![(dest, packet.clone()), (dest, vec![1])]);
+        assert_eq!(found.send_data(dest, vec![2]), Some(dest));
+
+        // Not found after RREQ_RETRIES
+        let mut lost = node(own_ip);
+        lost.send_data(dest, packet.clone());
+        lost.send_data(dest, vec![1]);
+        while lost.discovering(&dest) {
+            lost.discoveries.get_mut(&dest).unwrap().deadline = Utc::now();
+            lost.tick();
+        }
+        assert!(lost.ready.is_empty());
+        assert!(lost.buffered.is_empty());
+        assert_eq!(lost.dropped_packets(), 2);
+        // Only the IPv4 packet can be answered
+        assert_eq!(lost.unreachable.len(), 1);
+        assert_eq!(&lost.unreachable[0][16..20], &own_ip.octets());
+
+        // Uncollected packets and overflowing queues are counted as dropped
+        let mut full = node(own_ip);
+        full.config.buffer_max_packets = 2;
+        for i in 0..3 {
+            full.send_data(dest, vec![i]);
+        }
+        full.update_neighbour(dest);
+        full.tick();
+        assert_eq!(full.ready, vec![(dest, vec![1]), (dest, vec![2])]);
+        assert_eq!(full.dropped_packets(), 1);
+        full.routes.invalidate(&dest);
+        full.send_data(dest, vec![3]);
+        full.update_neighbour(dest);
+        full.tick();
+        assert_eq!(full.ready, vec![(dest, vec![2]), (dest, vec![3])]);
+        assert_eq!(full.dropped_packets(), 2);
+    }
+}
@@ -0,0 +1,415 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Utc};
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, SocketAddrV4};
+use std::thread;
+use std::time::Instant;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use bytes::BufMut;
+
+use super::buffer::checksum;
+use super::node::Node;
+use super::{AodvMessage, ParseError, AODV_PORT};
+
+/// Magic number of a pcap file with microsecond timestamps, in the byte order of the file
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+/// Magic number of a pcap file with nanosecond timestamps
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+/// First four bytes of a pcapng file, which this module doesn't read
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+/// Longest packet kept in the captures we write
+const SNAPLEN: u32 = 65_535;
+/// Longest packet accepted from captures we read, the largest snapshot length tcpdump uses
+const MAX_PACKET: u32 = 262_144;
+
+// Link types from tcpdump.org/linktypes.html
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+
+/// A UDP datagram carrying AODV messages, as seen on the wire
+#[derive(Clone, Debug, PartialEq)]
+pub struct Datagram {
+    pub time: DateTime<Utc>, // When it was sent or received
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+    pub ttl: u8,          // IP TTL
+    pub payload: Vec<u8>, // The messages themselves
+}
+
+/// Writes datagrams to a pcap file as raw IPv4 packets, which Wireshark's AODV dissector picks
+/// up by their port
+pub struct PcapWriter<W: Write> {
+    out: W,
+    ident: u16, // IP Identification of the next packet
+}
+
+/// A capture being written straight to disk
+pub type FileWriter = PcapWriter<BufWriter<File>>;
+
+impl FileWriter {
+    /// Start a capture in `path`, replacing anything already there
+    pub fn create(path: &str) -> io::Result<Self> {
+        PcapWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write the pcap file header to `out`
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.put_u32_le(MAGIC_MICROS);
+        header.put_u16_le(2); // Version 2.4
+        header.put_u16_le(4);
+        header.put_u32_le(0); // Timestamps are in UTC
+        header.put_u32_le(0); // Accuracy of the timestamps
+        header.put_u32_le(SNAPLEN);
+        header.put_u32_le(LINKTYPE_RAW);
+        out.write_all(&header)?;
+        Ok(PcapWriter { out, ident: 0 })
+    }
+    /// Append `d` with IPv4 and UDP headers made up for it
+    pub fn write(&mut self, d: &Datagram) -> io::Result<()> {
+        let packet = ipv4_udp(d, self.ident);
+        self.ident = self.ident.wrapping_add(1);
+
+        let mut header = Vec::with_capacity(16);
+        header.put_u32_le(d.time.timestamp() as u32);
+        header.put_u32_le(d.time.timestamp_subsec_micros());
+        header.put_u32_le(packet.len() as u32);
+        header.put_u32_le(packet.len() as u32);
+        self.out.write_all(&header)?;
+        self.out.write_all(&packet)
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+    /// Return the underlying writer
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Return `d` as an IPv4 packet. The UDP checksum is optional over IPv4 so it is left out.
+fn ipv4_udp(d: &Datagram, ident: u16) -> Vec<u8> {
+    let udp_len = 8 + d.payload.len();
+    let mut b = Vec::with_capacity(20 + udp_len);
+    b.put_u8(0x45); // Version 4 and a 20 byte header
+    b.put_u8(0);
+    b.put_u16_be((20 + udp_len) as u16);
+    b.put_u16_be(ident);
+    b.put_u16_be(0); // Flags and fragment offset
+    b.put_u8(d.ttl);
+    b.put_u8(17); // UDP
+    b.put_u16_be(0); // Header checksum, filled in below
+    b.put_slice(&d.src.ip().octets());
+    b.put_slice(&d.dst.ip().octets());
+    let sum = checksum(&b);
+    BigEndian::write_u16(&mut b[10..12], sum);
+
+    b.put_u16_be(d.src.port());
+    b.put_u16_be(d.dst.port());
+    b.put_u16_be(udp_len as u16);
+    b.put_u16_be(0);
+    b.put_slice(&d.payload);
+    b
+}
+
+/// Reads the AODV datagrams out of a pcap file, whether it was written by `PcapWriter` or by
+/// tcpdump on an Ethernet or Linux cooked interface.
+///
+/// pcapng files aren't understood, but `editcap -F pcap` converts them.
+pub struct PcapReader<R: Read> {
+    input: R,
+    swapped: bool, // Written on a big endian machine
+    nanos: bool,   // Timestamps are in nanoseconds rather than microseconds
+    linktype: u32,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open(path: &str) -> io::Result<Self> {
+        PcapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read the pcap file header from `input`
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0; 24];
+        input.read_exact(&mut header)?;
+        let magic = LittleEndian::read_u32(&header[..4]);
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            m if m.swap_bytes() == MAGIC_MICROS => (true, false),
+            m if m.swap_bytes() == MAGIC_NANOS => (true, true),
+            PCAPNG_MAGIC => return Err(invalid("pcapng isn't supported, convert it to pcap")),
+            _ => return Err(invalid("not a pcap file")),
+        };
+        let mut reader = PcapReader {
+            input,
+            swapped,
+            nanos,
+            linktype: 0,
+        };
+        reader.linktype = reader.u32(&header[20..24]);
+        Ok(reader)
+    }
+    /// Return the link type of every packet in the file
+    pub fn linktype(&self) -> u32 {
+        self.linktype
+    }
+    /// Return the next packet and when it was captured, or None at the end of the file
+    pub fn next_packet(&mut self) -> io::Result<Option<(DateTime<Utc>, Vec<u8>)>> {
+        let mut header = [0; 16];
+        let read = self.input.read(&mut header)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.input.read_exact(&mut header[read..])?;
+
+        let secs = i64::from(self.u32(&header[..4]));
+        let frac = self.u32(&header[4..8]);
+        let nanos = if self.nanos {
+            frac
+        } else {
+            frac.saturating_mul(1000)
+        };
+        let time = DateTime::from_timestamp(secs, nanos).ok_or_else(|| invalid("bad timestamp"))?;
+
+        let len = self.u32(&header[8..12]);
+        if len > MAX_PACKET {
+            return Err(invalid("packet longer than any snapshot length"));
+        }
+        let mut packet = vec![0; len as usize];
+        self.input.read_exact(&mut packet)?;
+        Ok(Some((time, packet)))
+    }
+    fn u32(&self, b: &[u8]) -> u32 {
+        if self.swapped {
+            BigEndian::read_u32(b)
+        } else {
+            LittleEndian::read_u32(b)
+        }
+    }
+}
+
+/// Every packet that is an unfragmented IPv4 UDP datagram to or from `AODV_PORT`
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<Datagram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_packet() {
+                Ok(Some((time, packet))) => match datagram(self.linktype, time, &packet) {
+                    Some(d) if d.src.port() == AODV_PORT || d.dst.port() == AODV_PORT => {
+                        return Some(Ok(d))
+                    }
+                    _ => {}
+                },
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Return the UDP datagram in a captured packet, if that is what it holds
+fn datagram(linktype: u32, time: DateTime<Utc>, packet: &[u8]) -> Option<Datagram> {
+    const IPV4: &[u8] = &[0x08, 0x00];
+    let ip = match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV4 => packet,
+        LINKTYPE_ETHERNET if packet.get(12..14)? == IPV4 => &packet[14..],
+        LINKTYPE_LINUX_SLL if packet.get(14..16)? == IPV4 => &packet[16..],
+        _ => return None,
+    };
+    if ip.len() < 20 || ip[0] >> 4 != 4 || ip[9] != 17 {
+        return None;
+    }
+    // Fragments would have to be put back together first
+    if ip[6] & 0x3f != 0 || ip[7] != 0 {
+        return None;
+    }
+    let header_len = (ip[0] & 0x0f) as usize * 4;
+    // Ethernet pads short frames, so go by the IP total length
+    let ip = ip.get(..BigEndian::read_u16(&ip[2..4]) as usize)?;
+    let udp = ip.get(header_len..)?;
+    if udp.len() < 8 {
+        return None;
+    }
+    let payload = udp.get(8..BigEndian::read_u16(&udp[4..6]) as usize)?;
+    Some(Datagram {
+        time,
+        src: SocketAddrV4::new(
+            BigEndian::read_u32(&ip[12..16]).into(),
+            BigEndian::read_u16(&udp[..2]),
+        ),
+        dst: SocketAddrV4::new(
+            BigEndian::read_u32(&ip[16..20]).into(),
+            BigEndian::read_u16(&udp[2..4]),
+        ),
+        ttl: ip[8],
+        payload: payload.to_vec(),
+    })
+}
+
+/// Hand the messages of a received datagram to `node` one after another, the way the daemon does.
+///
+/// Return how many were handled, and the error that cut the rest of the datagram off if any.
+pub fn handle_datagram(d: &Datagram, node: &mut Node) -> (usize, Option<ParseError>) {
+    let addr = SocketAddr::V4(d.src);
+    let mut handled = 0;
+    // The TTL tells a Hello from a RREP for a neighbour's own address
+    for parsed in AodvMessage::parse_iter_with_ttl(&d.payload, d.ttl) {
+        match parsed {
+            Ok(msg) => {
+                println!("{} from {}", msg, addr);
+                msg.handle_message(&addr, d.ttl, node);
+                handled += 1;
+            }
+            Err(e) => return (handled, Some(e)),
+        }
+    }
+    (handled, None)
+}
+
+/// Feed captured datagrams through `handle_datagram` as if they had just arrived, returning how
+/// many messages were handled.
+///
+/// With `realtime` set the gaps between datagrams are waited out so the node's timers see the
+/// original timing. Datagrams the node sent itself are skipped, and whatever it would send in
+/// reply is printed instead.
+pub fn replay<I>(datagrams: I, node: &mut Node, realtime: bool) -> io::Result<usize>
+where
+    I: IntoIterator<Item = io::Result<Datagram>>,
+{
+    let start = Instant::now();
+    let mut first = None;
+    let mut handled = 0;
+    for d in datagrams {
+        let d = d?;
+        if *d.src.ip() == node.config.current_ip {
+            continue;
+        }
+        if realtime {
+            let first = *first.get_or_insert(d.time);
+            let offset = (d.time - first).to_std().unwrap_or_default();
+            if let Some(wait) = offset.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+
+        let (n, malformed) = handle_datagram(&d, node);
+        handled += n;
+        if let Some(e) = malformed {
+            eprintln!("Malformed datagram from {}: {}", d.src, e);
+        }
+        node.tick();
+        for out in node.outbox.drain(..) {
+            println!("{} to {}", out.msg, out.dest);
+        }
+    }
+    Ok(handled)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test_pcap {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::Ipv4Addr;
+
+    use super::super::config::Config;
+    use super::super::AodvMessage;
+
+    fn rreq_datagram() -> Datagram {
+        let msg: AodvMessage =
+            "RREQ id=7 flags=U hops=2 dest=192.168.10.14/0 orig=192.168.10.19/63"
+                .parse()
+                .unwrap();
+        Datagram {
+            time: DateTime::from_timestamp(1_508_437_200, 123_456_000).unwrap(),
+            src: SocketAddrV4::new(Ipv4Addr::new(192, 168, 10, 8), AODV_PORT),
+            dst: SocketAddrV4::new(Ipv4Addr::new(255, 255, 255, 255), AODV_PORT),
+            ttl: 5,
+            payload: msg.bit_message(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let d = rreq_datagram();
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        writer.write(&d).unwrap();
+        writer.write(&d).unwrap();
+        let b = writer.into_inner();
+        assert_eq!(b.len(), 24 + 2 * (16 + 28 + 24));
+        // A valid IPv4 header sums to zero
+        assert_eq!(checksum(&b[40..60]), 0);
+
+        let reader = PcapReader::new(Cursor::new(b)).unwrap();
+        assert_eq!(reader.linktype(), LINKTYPE_RAW);
+        let read: Vec<Datagram> = reader.map(Result::unwrap).collect();
+        assert_eq!(read, vec![d.clone(), d]);
+    }
+
+    #[test]
+    fn test_ethernet() {
+        let d = rreq_datagram();
+        let mut b = Vec::new();
+        b.put_u32_be(MAGIC_MICROS); // Written big endian
+        b.put_slice(&[
+            0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 0, 0, 0, 1,
+        ]);
+
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend(ipv4_udp(&d, 0));
+        frame.extend_from_slice(&[0; 6]); // Padding
+        b.put_u32_be(1_508_437_200);
+        b.put_u32_be(123_456);
+        b.put_u32_be(frame.len() as u32);
+        b.put_u32_be(frame.len() as u32);
+        b.extend(frame);
+
+        let read: Vec<Datagram> = PcapReader::new(Cursor::new(b))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read, vec![d]);
+
+        let pcapng = Cursor::new(vec![
+            0x0a, 0x0d, 0x0d, 0x0a, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        assert!(PcapReader::new(pcapng).is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut node = Node::new(Config {
+            current_ip: Ipv4Addr::new(192, 168, 10, 5),
+            ..Config::default()
+        });
+        let own = Datagram {
+            src: SocketAddrV4::new(node.config.current_ip, AODV_PORT),
+            ..rreq_datagram()
+        };
+        let handled = replay(vec![Ok(own), Ok(rreq_datagram())], &mut node, false).unwrap();
+
+        assert_eq!(handled, 1);
+        // The RREQ left a route back to its originator
+        assert!(node
+            .routes
+            .get_valid(&Ipv4Addr::new(192, 168, 10, 19))
+            .is_some());
+        assert!(node.outbox.is_empty());
+    }
+}
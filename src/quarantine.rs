@@ -0,0 +1,106 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Duration, Utc};
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Counts malformed datagrams by source and stops listening to sources that send too many
+#[derive(Debug)]
+pub struct Quarantine {
+    threshold: usize, // Malformed datagrams within `period` before a source is ignored, 0 for never
+    period: Duration, // How long to count for, and how long to ignore a source for
+    sources: HashMap<IpAddr, (usize, DateTime<Utc>)>, // Count so far and when it resets
+    quarantined: HashMap<IpAddr, DateTime<Utc>>, // Ignored sources and when to listen again
+    malformed: usize,
+}
+
+impl Quarantine {
+    pub fn new(threshold: usize, period: Duration) -> Self {
+        Quarantine {
+            threshold,
+            period,
+            sources: HashMap::new(),
+            quarantined: HashMap::new(),
+            malformed: 0,
+        }
+    }
+    /// Count a malformed datagram from `source`, returning whether it is now quarantined
+    pub fn record(&mut self, source: IpAddr) -> bool {
+        self.record_at(source, Utc::now())
+    }
+    /// Return whether datagrams from `source` should be ignored
+    pub fn quarantined(&self, source: &IpAddr) -> bool {
+        self.quarantined_at(source, Utc::now())
+    }
+    /// Return how many malformed datagrams were seen so far
+    pub fn malformed(&self) -> usize {
+        self.malformed
+    }
+
+    fn record_at(&mut self, source: IpAddr, now: DateTime<Utc>) -> bool {
+        self.malformed += 1;
+        self.sources.retain(|_, &mut (_, reset)| reset > now);
+        self.quarantined.retain(|_, until| *until > now);
+        if self.threshold == 0 {
+            return false;
+        }
+
+        let period = self.period;
+        let count = {
+            let entry = self.sources.entry(source).or_insert((0, now + period));
+            entry.0 += 1;
+            entry.0
+        };
+        if count < self.threshold {
+            return false;
+        }
+        self.sources.remove(&source);
+        self.quarantined.insert(source, now + period);
+        true
+    }
+    fn quarantined_at(&self, source: &IpAddr, now: DateTime<Utc>) -> bool {
+        match self.quarantined.get(source) {
+            Some(until) => *until > now,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_quarantine {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_quarantine() {
+        let now = Utc::now();
+        let bad = IpAddr::V4(Ipv4Addr::new(192, 168, 10, 66));
+        let good = IpAddr::V4(Ipv4Addr::new(192, 168, 10, 2));
+        let mut q = Quarantine::new(3, Duration::seconds(10));
+
+        assert!(!q.record_at(bad, now));
+        assert!(!q.record_at(good, now));
+        assert!(!q.record_at(bad, now));
+        assert!(q.record_at(bad, now));
+        assert!(q.quarantined_at(&bad, now));
+        assert!(!q.quarantined_at(&good, now));
+        assert_eq!(q.malformed(), 4);
+
+        // Listened to again once the period is over, and counting starts over
+        let later = now + Duration::seconds(10);
+        assert!(!q.quarantined_at(&bad, later));
+        assert!(!q.record_at(good, later));
+        assert!(!q.record_at(good, later));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let bad = IpAddr::V4(Ipv4Addr::new(192, 168, 10, 66));
+        let mut q = Quarantine::new(0, Duration::seconds(10));
+        for _ in 0..100 {
+            assert!(!q.record(bad));
+        }
+        assert!(!q.quarantined(&bad));
+    }
+}
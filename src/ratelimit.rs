@@ -0,0 +1,139 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Duration, Utc};
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// What to do with a message that would go over the rate limit
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum RateLimitPolicy {
+    Queue, // Hold it until the limit allows it, up to one second's worth of messages
+    Drop,  // Throw it away
+}
+
+impl FromStr for RateLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "queue" => Ok(RateLimitPolicy::Queue),
+            "drop" => Ok(RateLimitPolicy::Drop),
+            _ => Err(format!("Unknown rate limit policy: {}", s)),
+        }
+    }
+}
+
+/// Limits how many messages per second a node originates, like RREQ_RATELIMIT and
+/// RERR_RATELIMIT in section 10. of the RFC
+#[derive(Debug)]
+pub struct RateLimiter<T> {
+    limit: usize,
+    policy: RateLimitPolicy,
+    sent: VecDeque<DateTime<Utc>>, // When each message in the last second was let through
+    queue: VecDeque<T>,
+    dropped: usize,
+}
+
+impl<T> RateLimiter<T> {
+    /// Return a limiter allowing `limit` messages per second
+    pub fn new(limit: usize, policy: RateLimitPolicy) -> Self {
+        RateLimiter {
+            limit,
+            policy,
+            sent: VecDeque::new(),
+            queue: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+    /// Return the message if it may be sent now, otherwise queue or drop it
+    pub fn offer(&mut self, msg: T) -> Option<T> {
+        self.offer_at(msg, Utc::now())
+    }
+    /// Return the queued messages that may now be sent
+    pub fn release(&mut self) -> Vec<T> {
+        self.release_at(Utc::now())
+    }
+    /// Return how many messages were dropped so far
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+    /// Return how many messages are waiting for the limit to allow them
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn offer_at(&mut self, msg: T, now: DateTime<Utc>) -> Option<T> {
+        if self.queue.is_empty() && self.allow(now) {
+            return Some(msg);
+        }
+        match self.policy {
+            RateLimitPolicy::Queue if self.queue.len() < self.limit => self.queue.push_back(msg),
+            _ => self.dropped += 1,
+        }
+        None
+    }
+    fn release_at(&mut self, now: DateTime<Utc>) -> Vec<T> {
+        let mut released = Vec::new();
+        while !self.queue.is_empty() && self.allow(now) {
+            released.extend(self.queue.pop_front());
+        }
+        released
+    }
+    /// Record a message as sent if there is room in the last second for it
+    fn allow(&mut self, now: DateTime<Utc>) -> bool {
+        let window = now - Duration::seconds(1);
+        while let Some(&t) = self.sent.front() {
+            if t > window {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.limit {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test_ratelimit {
+    use super::*;
+
+    #[test]
+    fn test_queue_policy() {
+        let now = Utc::now();
+        let mut limiter = RateLimiter::new(2, RateLimitPolicy::Queue);
+        assert_eq!(limiter.offer_at(1, now), Some(1));
+        assert_eq!(limiter.offer_at(2, now), Some(2));
+        assert_eq!(limiter.offer_at(3, now), None);
+        assert_eq!(limiter.offer_at(4, now), None);
+        // The queue holds at most one second's worth
+        assert_eq!(limiter.offer_at(5, now), None);
+        assert_eq!((limiter.queued(), limiter.dropped()), (2, 1));
+
+        assert!(limiter.release_at(now).is_empty());
+        let later = now + Duration::milliseconds(1001);
+        assert_eq!(limiter.release_at(later), vec![3, 4]);
+        assert_eq!(limiter.offer_at(6, later), None);
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        let now = Utc::now();
+        let mut limiter = RateLimiter::new(1, RateLimitPolicy::Drop);
+        assert_eq!(limiter.offer_at(1, now), Some(1));
+        assert_eq!(limiter.offer_at(2, now), None);
+        assert_eq!((limiter.queued(), limiter.dropped()), (0, 1));
+        assert_eq!(limiter.offer_at(3, now + Duration::seconds(1)), Some(3));
+    }
+}
@@ -0,0 +1,253 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Duration, Utc};
+
+use std::collections::hash_map::{self, HashMap};
+use std::net::Ipv4Addr;
+
+use super::config::Config;
+use super::seq::SeqNum;
+
+/// The state of a route table entry as per section 2. of the RFC
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteState {
+    Valid,
+    Invalid,
+    Repairing,
+}
+
+/// A single entry in the routing table
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteEntry {
+    pub dest_ip: Ipv4Addr,         // Destination IP Address
+    pub dest_seq_num: SeqNum,      // Destination Sequence Number
+    pub valid_seq_num: bool,       // Valid Destination Sequence Number flag
+    pub state: RouteState,         // Valid, Invalid or Repairing
+    pub interface: String,         // Network Interface
+    pub hop_count: u8,             // Hop Count (number of hops needed to reach destination)
+    pub next_hop: Ipv4Addr,        // Next Hop
+    pub precursors: Vec<Ipv4Addr>, // List of Precursors
+    pub lifetime: DateTime<Utc>,   // Expiration or deletion time of the route
+}
+
+impl RouteEntry {
+    /// Return whether this route can currently be used to forward packets
+    pub fn is_valid(&self) -> bool {
+        self.state == RouteState::Valid && self.lifetime > Utc::now()
+    }
+    /// Add a neighbour to the precursor list if it isn't already there
+    pub fn add_precursor(&mut self, ip: Ipv4Addr) {
+        if !self.precursors.contains(&ip) {
+            self.precursors.push(ip);
+        }
+    }
+    /// Extend the lifetime to at least `lifetime` from now
+    pub fn extend_lifetime(&mut self, lifetime: Duration) {
+        let expiry = Utc::now() + lifetime;
+        if expiry > self.lifetime {
+            self.lifetime = expiry;
+        }
+    }
+}
+
+/// The routing table of an aodv node, keyed by destination
+#[derive(Debug)]
+pub struct RouteTable {
+    routes: HashMap<Ipv4Addr, RouteEntry>,
+    active_route_timeout: Duration,
+    delete_period: Duration,
+}
+
+impl RouteTable {
+    /// Return an empty routing table using the timers from `config`
+    pub fn new(config: &Config) -> Self {
+        RouteTable {
+            routes: HashMap::new(),
+            active_route_timeout: config.ACTIVE_ROUTE_TIMEOUT,
+            delete_period: config.DELETE_PERIOD,
+        }
+    }
+    /// Return the entry for `dest`, whatever its state
+    pub fn get(&self, dest: &Ipv4Addr) -> Option<&RouteEntry> {
+        self.routes.get(dest)
+    }
+    /// Return a mutable reference to the entry for `dest`, whatever its state
+    pub fn get_mut(&mut self, dest: &Ipv4Addr) -> Option<&mut RouteEntry> {
+        self.routes.get_mut(dest)
+    }
+    /// Return the entry for `dest` only if it is a usable route
+    pub fn get_valid(&self, dest: &Ipv4Addr) -> Option<&RouteEntry> {
+        self.routes.get(dest).filter(|r| r.is_valid())
+    }
+    /// Insert or update a route as per section 6.2 of the RFC.
+    ///
+    /// The existing entry is only replaced when the new one has a fresher sequence number, an
+    /// equal sequence number with fewer hops, or when the existing entry has no valid sequence
+    /// number or isn't valid. Precursors and any longer lifetime are kept. Return whether the
+    /// table changed.
+    pub fn update(&mut self, mut entry: RouteEntry) -> bool {
+        match self.routes.entry(entry.dest_ip) {
+            hash_map::Entry::Vacant(v) => {
+                v.insert(entry);
+                true
+            }
+            hash_map::Entry::Occupied(mut o) => {
+                let old = o.get_mut();
+                let replace = !old.valid_seq_num
+                    || !entry.valid_seq_num && old.state != RouteState::Valid
+                    || entry.valid_seq_num && entry.dest_seq_num > old.dest_seq_num
+                    || entry.valid_seq_num
+                        && entry.dest_seq_num == old.dest_seq_num
+                        && (entry.hop_count < old.hop_count || old.state != RouteState::Valid);
+                if !replace {
+                    return false;
+                }
+                if !entry.valid_seq_num {
+                    entry.dest_seq_num = old.dest_seq_num;
+                }
+                for ip in old.precursors.drain(..) {
+                    entry.add_precursor(ip);
+                }
+                if old.state == RouteState::Valid && old.lifetime > entry.lifetime {
+                    entry.lifetime = old.lifetime;
+                }
+                *old = entry;
+                true
+            }
+        }
+    }
+    /// Extend the lifetime of a valid route to `dest` by ACTIVE_ROUTE_TIMEOUT
+    pub fn refresh(&mut self, dest: &Ipv4Addr) {
+        let timeout = self.active_route_timeout;
+        if let Some(route) = self.routes.get_mut(dest) {
+            if route.state == RouteState::Valid {
+                route.extend_lifetime(timeout);
+            }
+        }
+    }
+    /// Mark the route to `dest` invalid, incrementing its sequence number and scheduling its
+    /// deletion after DELETE_PERIOD as per section 6.11 of the RFC
+    pub fn invalidate(&mut self, dest: &Ipv4Addr) -> Option<&RouteEntry> {
+        let delete_period = self.delete_period;
+        let route = self.routes.get_mut(dest)?;
+        if route.state != RouteState::Invalid {
+            // Routes under repair already had theirs incremented
+            if route.valid_seq_num && route.state == RouteState::Valid {
+                route.dest_seq_num = route.dest_seq_num.next();
+            }
+            route.state = RouteState::Invalid;
+            route.lifetime = Utc::now() + delete_period;
+        }
+        Some(route)
+    }
+    /// Return the destinations of every valid route through `next_hop`
+    pub fn routes_via(&self, next_hop: &Ipv4Addr) -> Vec<Ipv4Addr> {
+        self.routes
+            .values()
+            .filter(|r| r.state == RouteState::Valid && r.next_hop == *next_hop)
+            .map(|r| r.dest_ip)
+            .collect()
+    }
+    /// Invalidate expired routes and delete invalid routes older than DELETE_PERIOD
+    pub fn purge(&mut self) {
+        let now = Utc::now();
+        let delete_period = self.delete_period;
+        self.routes
+            .retain(|_, r| r.state == RouteState::Valid || r.lifetime > now);
+        for route in self.routes.values_mut() {
+            if route.state == RouteState::Valid && route.lifetime <= now {
+                route.state = RouteState::Invalid;
+                route.lifetime = now + delete_period;
+            }
+        }
+    }
+    /// Iterate over every entry in the table
+    pub fn iter(&self) -> impl Iterator<Item = &RouteEntry> {
+        self.routes.values()
+    }
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test_routing {
+    use super::*;
+
+    fn entry(dest: Ipv4Addr, seq: u32, hops: u8) -> RouteEntry {
+        RouteEntry {
+            dest_ip: dest,
+            dest_seq_num: SeqNum(seq),
+            valid_seq_num: true,
+            state: RouteState::Valid,
+            interface: String::from("wlan0"),
+            hop_count: hops,
+            next_hop: Ipv4Addr::new(192, 168, 10, 1),
+            precursors: Vec::new(),
+            lifetime: Utc::now() + Duration::milliseconds(3000),
+        }
+    }
+
+    #[test]
+    fn test_update_rules() {
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut table = RouteTable::new(&Config::default());
+
+        assert!(table.update(entry(dest, 5, 3)));
+        table
+            .get_mut(&dest)
+            .unwrap()
+            .add_precursor(Ipv4Addr::new(192, 168, 10, 2));
+
+        // Staler sequence number is ignored
+        assert!(!table.update(entry(dest, 4, 1)));
+        // Same sequence number but longer is ignored
+        assert!(!table.update(entry(dest, 5, 4)));
+        // Same sequence number but shorter replaces
+        assert!(table.update(entry(dest, 5, 2)));
+        // Fresher sequence number replaces even if longer
+        assert!(table.update(entry(dest, 6, 9)));
+
+        let route = table.get_valid(&dest).unwrap();
+        assert_eq!((route.dest_seq_num, route.hop_count), (SeqNum(6), 9));
+        assert_eq!(route.precursors, vec![Ipv4Addr::new(192, 168, 10, 2)]);
+    }
+
+    #[test]
+    fn test_update_across_rollover() {
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut table = RouteTable::new(&Config::default());
+
+        table.update(entry(dest, u32::MAX, 3));
+        assert!(table.update(entry(dest, 1, 5)));
+        assert!(!table.update(entry(dest, u32::MAX - 1, 1)));
+        assert_eq!(table.get(&dest).unwrap().dest_seq_num, SeqNum(1));
+    }
+
+    #[test]
+    fn test_invalidate_and_purge() {
+        let dest = Ipv4Addr::new(192, 168, 10, 14);
+        let mut table = RouteTable::new(&Config::default());
+        table.update(entry(dest, 5, 3));
+
+        assert_eq!(table.invalidate(&dest).unwrap().dest_seq_num, SeqNum(6));
+        assert!(table.get_valid(&dest).is_none());
+        assert!(table.routes_via(&Ipv4Addr::new(192, 168, 10, 1)).is_empty());
+
+        // Still remembered until DELETE_PERIOD passes
+        table.purge();
+        assert_eq!(table.len(), 1);
+        table.get_mut(&dest).unwrap().lifetime = Utc::now() - Duration::milliseconds(1);
+        table.purge();
+        assert!(table.is_empty());
+
+        // Expired valid routes become invalid first
+        table.update(entry(dest, 7, 3));
+        table.get_mut(&dest).unwrap().lifetime = Utc::now() - Duration::milliseconds(1);
+        table.purge();
+        assert_eq!(table.get(&dest).unwrap().state, RouteState::Invalid);
+    }
+}
@@ -0,0 +1,112 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A destination sequence number.
+///
+/// Sequence numbers are compared using signed 32-bit arithmetic as per section 6.1 of the RFC,
+/// so a number that has just rolled over past `u32::MAX` is still fresher than one just before
+/// it. Two numbers exactly half the number space apart are unordered, as neither is fresher.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SeqNum(pub u32);
+
+impl SeqNum {
+    /// Return the sequence number after this one
+    pub fn next(self) -> SeqNum {
+        SeqNum(self.0.wrapping_add(1))
+    }
+}
+
+impl PartialOrd for SeqNum {
+    fn partial_cmp(&self, other: &SeqNum) -> Option<Ordering> {
+        match self.0.wrapping_sub(other.0) as i32 {
+            i32::MIN => None,
+            diff => Some(diff.cmp(&0)),
+        }
+    }
+}
+
+impl From<u32> for SeqNum {
+    fn from(n: u32) -> Self {
+        SeqNum(n)
+    }
+}
+
+impl From<SeqNum> for u32 {
+    fn from(n: SeqNum) -> Self {
+        n.0
+    }
+}
+
+impl fmt::Display for SeqNum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The sequence number a node owns for its own address.
+///
+/// It only ever moves forward, and only right before the node originates a RREQ or answers one
+/// as the destination, as per section 6.1 of the RFC.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OwnSeqNum(SeqNum);
+
+impl OwnSeqNum {
+    pub fn new(start: SeqNum) -> Self {
+        OwnSeqNum(start)
+    }
+    /// Return the current sequence number
+    pub fn get(&self) -> SeqNum {
+        self.0
+    }
+    /// Increment the sequence number before originating a RREQ and return it
+    pub fn before_rreq(&mut self) -> SeqNum {
+        self.0 = self.0.next();
+        self.0
+    }
+    /// Catch up with the sequence number a RREQ asked for, if any, then increment it before
+    /// answering as the destination and return it
+    pub fn before_rrep(&mut self, requested: Option<SeqNum>) -> SeqNum {
+        if let Some(requested) = requested {
+            if requested > self.0 {
+                self.0 = requested;
+            }
+        }
+        self.0 = self.0.next();
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test_seq {
+    use super::*;
+
+    #[test]
+    fn test_rollover_comparison() {
+        assert!(SeqNum(5) > SeqNum(4));
+        assert!(SeqNum(0) > SeqNum(u32::MAX));
+        assert!(SeqNum(3) > SeqNum(u32::MAX - 3));
+        assert!(SeqNum(u32::MAX).next() > SeqNum(u32::MAX));
+        assert!(SeqNum((1 << 31) - 1) > SeqNum(0));
+        assert!(SeqNum((1 << 31) + 1) < SeqNum(0));
+    }
+
+    #[test]
+    fn test_half_space_unordered() {
+        // 2^31 apart neither number is fresher, whichever way round they're compared
+        let (a, b) = (SeqNum(0), SeqNum(1 << 31));
+        assert_eq!(a.partial_cmp(&b), None);
+        assert_eq!(b.partial_cmp(&a), None);
+        assert_eq!([a < b, a > b, b < a, b > a, a >= b, b >= a], [false; 6]);
+    }
+
+    #[test]
+    fn test_own_seq_num() {
+        let mut own = OwnSeqNum::new(SeqNum(u32::MAX));
+        assert_eq!(own.before_rreq(), SeqNum(0));
+        assert_eq!(own.before_rrep(None), SeqNum(1));
+        // A stale request doesn't take us backwards
+        assert_eq!(own.before_rrep(Some(SeqNum(u32::MAX))), SeqNum(2));
+        assert_eq!(own.before_rrep(Some(SeqNum(9))), SeqNum(10));
+        assert_eq!(own.get(), SeqNum(10));
+    }
+}
@@ -0,0 +1,74 @@
+/// Serialize a `chrono::Duration` as whole milliseconds, the unit of the YAML config
+pub mod millis {
+    extern crate chrono;
+
+    use self::chrono::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_i64(d.num_milliseconds())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        i64::deserialize(d).map(Duration::milliseconds)
+    }
+}
+
+/// Serialize the prefix size of a RREP as is, refusing one that doesn't fit its 5 bits
+pub mod prefix_size {
+    use serde::de::{Error, Unexpected};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(prefix_size: &u8, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u8(*prefix_size)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u8, D::Error> {
+        let prefix_size = u8::deserialize(d)?;
+        if prefix_size > 31 {
+            return Err(D::Error::invalid_value(
+                Unexpected::Unsigned(u64::from(prefix_size)),
+                &"a prefix size of at most 31",
+            ));
+        }
+        Ok(prefix_size)
+    }
+}
+
+/// Serialize the unreachable destinations of a RERR as `{"ip", "seq_num"}` objects, refusing a
+/// list the DestCount field couldn't describe
+pub mod udests {
+    use std::net::Ipv4Addr;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::super::RERR;
+
+    #[derive(Serialize, Deserialize)]
+    struct Udest {
+        ip: Ipv4Addr,
+        seq_num: u32,
+    }
+
+    pub fn serialize<S>(udests: &[(Ipv4Addr, u32)], s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.collect_seq(udests.iter().map(|&(ip, seq_num)| Udest { ip, seq_num }))
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Vec<(Ipv4Addr, u32)>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let udests = Vec::<Udest>::deserialize(d)?;
+        if udests.is_empty() || udests.len() > RERR::MAX_DESTS {
+            return Err(D::Error::invalid_length(
+                udests.len(),
+                &"between 1 and 255 destinations",
+            ));
+        }
+        Ok(udests.into_iter().map(|u| (u.ip, u.seq_num)).collect())
+    }
+}
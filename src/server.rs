@@ -1,33 +1,242 @@
+extern crate chrono;
 extern crate futures;
 extern crate tokio;
-extern crate tokio_io;
+extern crate tokio_signal;
 
-use std::net::SocketAddr;
+use std::cell::RefCell;
+use std::net::{self, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::process::exit;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use self::futures::future;
+use self::chrono::Utc;
+use self::futures::future::{self, Future};
 use self::futures::stream::Stream;
-use self::tokio::executor::current_thread;
-use self::tokio::net::{UdpFramed, UdpSocket};
+use self::tokio::reactor::Handle;
+use self::tokio::runtime::current_thread;
+use self::tokio::timer::Interval;
+use self::tokio_signal::unix::{Signal, SIGINT, SIGTERM};
 
-use super::{AodvCodec, AODV_PORT};
+use super::config::Config;
+use super::node::{Node, Outgoing};
+use super::pcap::{handle_datagram, Datagram, FileWriter};
+use super::quarantine::Quarantine;
+use super::socket::Incoming;
+use super::state::SavedState;
+use super::{batch, AodvMessage, AODV_PORT, HEADER_LEN};
 
-pub fn aodv() {
+/// How often the node checks its timers, in milliseconds
+const TICK_INTERVAL: u64 = 50;
+
+/// Run the daemon. It only speaks the AODV control protocol: it has no data path of its own, so
+/// data packets reach a `Node` through `send_data` and `forward_data` in the library.
+pub fn aodv(config: Config) {
     // Bind to the AODV port
     let addr = SocketAddr::new("0.0.0.0".parse().unwrap(), AODV_PORT);
-    let socket = UdpSocket::bind(&addr).unwrap();
+    let std_socket = net::UdpSocket::bind(addr).unwrap();
+    std_socket.set_broadcast(true).unwrap();
+    let send_socket = std_socket.try_clone().unwrap();
+    let default_ttl = std_socket.ttl().unwrap();
+    let incoming = Incoming::new(std_socket, &Handle::default()).unwrap();
     println!("Started listening on {}", AODV_PORT);
 
-    let (_sink, stream) = UdpFramed::new(socket, AodvCodec).split();
+    // Unless we remember our own sequence number, don't answer anyone for a while
+    let state_file = config.state_file.clone();
+    let save_interval = match config.state_save_interval.to_std() {
+        Ok(interval) => interval,
+        Err(_) => {
+            eprintln!("StateSaveInterval must not be negative");
+            exit(1);
+        }
+    };
+    let mut node = Node::new(config);
+    match state_file {
+        Some(ref path) => match SavedState::load(path) {
+            Ok(state) => {
+                if node.restore(state) {
+                    println!("Restored state from {}", path);
+                } else {
+                    println!("State in {} is stale, staying quiet", path);
+                }
+            }
+            Err(e) => {
+                eprintln!("Unable to load state from {}: {}", path, e);
+                node.start_quiet_period();
+            }
+        },
+        None => node.start_quiet_period(),
+    }
+    let capture = node.config.capture_file.as_ref().and_then(|path| {
+        FileWriter::create(path)
+            .map_err(|e| eprintln!("Unable to capture to {}: {}", path, e))
+            .ok()
+    });
+    let capture = Rc::new(RefCell::new(capture));
+    let node = Rc::new(RefCell::new(node));
+
+    let receiver = {
+        let node = Rc::clone(&node);
+        let capture = Rc::clone(&capture);
+        let send_socket = send_socket.try_clone().unwrap();
+        let mut quarantine = {
+            let config = &node.borrow().config;
+            Quarantine::new(config.quarantine_threshold, config.quarantine_time)
+        };
+        // Neither socket errors nor bad datagrams may end the stream
+        incoming
+            .then(future::ok::<_, ()>)
+            .for_each(move |received| {
+                let d = match received {
+                    Ok(d) => d,
+                    Err(e) => {
+                        eprintln!("Unable to receive: {}", e);
+                        return future::ok(());
+                    }
+                };
+                // Capture what came off the wire before anything can go wrong decoding it
+                record(&capture, &d);
+                let addr = SocketAddr::V4(d.src);
+                let mut node = node.borrow_mut();
+                // Our own broadcasts come back to us
+                if *d.src.ip() == node.config.current_ip || quarantine.quarantined(&addr.ip()) {
+                    return future::ok(());
+                }
+                if let (_, Some(e)) = handle_datagram(&d, &mut node) {
+                    eprintln!(
+                        "Malformed datagram #{} from {}: {}",
+                        quarantine.malformed() + 1,
+                        addr,
+                        e
+                    );
+                    if quarantine.record(addr.ip()) {
+                        eprintln!("Too many malformed datagrams, ignoring {}", addr.ip());
+                    }
+                }
+                flush(&send_socket, default_ttl, &mut node, &capture);
+                future::ok(())
+            })
+    };
+
+    let timers = {
+        let node = Rc::clone(&node);
+        let state_file = state_file.clone();
+        let mut last_save = Instant::now();
+        let mut last_ids = (node.borrow().seq_num.get(), node.borrow().rreq_id);
+        let (mut discarded, mut last_dropped) = (0, 0);
+        Interval::new(Instant::now(), Duration::from_millis(TICK_INTERVAL))
+            .map_err(|err| eprintln!("{}", err))
+            .for_each(move |_| {
+                let mut node = node.borrow_mut();
+                node.tick();
+                flush(&send_socket, default_ttl, &mut node, &capture);
+
+                // With no data path there is nothing to send buffered packets or the ICMP errors
+                // for the ones given up on with. Those were counted as dropped already.
+                discarded += node.ready.drain(..).count();
+                node.unreachable.clear();
+                let dropped = node.dropped_packets() + discarded;
+                if dropped > last_dropped {
+                    eprintln!("Dropped {} data packets", dropped - last_dropped);
+                }
+                last_dropped = dropped;
+
+                // Save straight away when our own numbers move so a crash can't lose them
+                let ids = (node.seq_num.get(), node.rreq_id);
+                if let Some(ref path) = state_file {
+                    if ids != last_ids || last_save.elapsed() >= save_interval {
+                        save_state(path, &node);
+                        last_save = Instant::now();
+                        last_ids = ids;
+                    }
+                }
+                future::ok(())
+            })
+    };
+
+    current_thread::run(future::lazy(move || {
+        current_thread::spawn(receiver);
+        current_thread::spawn(timers);
+
+        // Save our state one last time on the way out
+        let shutdown = Signal::new(SIGINT)
+            .flatten_stream()
+            .select(Signal::new(SIGTERM).flatten_stream())
+            .into_future()
+            .map_err(|(err, _)| eprintln!("{}", err))
+            .map(move |_| {
+                if let Some(ref path) = state_file {
+                    save_state(path, &node.borrow());
+                }
+                exit(0)
+            });
+        current_thread::spawn(shutdown);
+        Ok(())
+    }))
+}
+
+/// Write the node's state to `path`
+fn save_state(path: &str, node: &Node) {
+    if let Err(e) = node.saved_state().save(path) {
+        eprintln!("Unable to save state to {}: {}", path, e);
+    }
+}
+
+/// Write a datagram to the capture file if there is one, giving up on capturing if that fails
+fn record(capture: &RefCell<Option<FileWriter>>, d: &Datagram) {
+    let mut capture = capture.borrow_mut();
+    let failed = match *capture {
+        Some(ref mut writer) => writer.write(d).and_then(|_| writer.flush()).err(),
+        None => None,
+    };
+    if let Some(e) = failed {
+        eprintln!("Unable to capture, stopping: {}", e);
+        *capture = None;
+    }
+}
+
+/// Send every message the node has queued up, sharing datagrams if aggregation is on
+fn flush(
+    socket: &net::UdpSocket,
+    default_ttl: u32,
+    node: &mut Node,
+    capture: &RefCell<Option<FileWriter>>,
+) {
+    let max_len = if node.config.aggregate_messages {
+        node.config.mtu.saturating_sub(HEADER_LEN)
+    } else {
+        0
+    };
 
-    let stream = stream
-        .map_err(|err| eprintln!("{}", err)) // BUG: Crashes when malformed packet is sent
-        .for_each(|(addr, msg)| {
-            println!("{:?}", addr);
-            println!("{:?}", msg);
-            future::ok(())
-        });
+    // Only messages to the same place with the same TTL can share a datagram
+    let mut groups: Vec<(Ipv4Addr, Option<u32>, Vec<AodvMessage>)> = Vec::new();
+    for Outgoing { dest, msg, ttl } in node.outbox.drain(..) {
+        match groups.iter_mut().find(|g| (g.0, g.1) == (dest, ttl)) {
+            Some(group) => group.2.push(msg),
+            None => groups.push((dest, ttl, vec![msg])),
+        }
+    }
 
-    current_thread::run(|_| {
-        current_thread::spawn(stream);
-    })
+    for (dest, ttl, msgs) in groups {
+        let ttl = ttl.unwrap_or(default_ttl);
+        if let Err(e) = socket.set_ttl(ttl) {
+            eprintln!("Unable to set TTL {}: {}", ttl, e);
+        }
+        let addr = SocketAddr::new(dest.into(), AODV_PORT);
+        for datagram in batch(&msgs, max_len) {
+            if let Err(e) = socket.send_to(&datagram, addr) {
+                eprintln!("Unable to send to {}: {}", addr, e);
+                continue;
+            }
+            record(
+                capture,
+                &Datagram {
+                    time: Utc::now(),
+                    src: SocketAddrV4::new(node.config.current_ip, AODV_PORT),
+                    dst: SocketAddrV4::new(dest, AODV_PORT),
+                    ttl: ttl as u8,
+                    payload: datagram.to_vec(),
+                },
+            );
+        }
+    }
 }
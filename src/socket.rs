@@ -0,0 +1,167 @@
+extern crate chrono;
+extern crate futures;
+extern crate libc;
+extern crate mio;
+extern crate tokio;
+
+use std::io;
+use std::mem;
+use std::net::{self, Ipv4Addr, SocketAddrV4};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+
+use self::chrono::Utc;
+use self::futures::{Async, Poll, Stream};
+use self::mio::Ready;
+use self::tokio::reactor::{Handle, PollEvented2};
+
+use super::pcap::Datagram;
+
+/// Largest UDP payload an IPv4 datagram can carry
+const MAX_PAYLOAD: usize = 65_507;
+
+/// The datagrams arriving on a UDP socket, along with the IP TTL and destination address the
+/// kernel reports for each through `recvmsg`
+pub struct Incoming {
+    io: PollEvented2<mio::net::UdpSocket>,
+    port: u16,    // Port the socket is bound to, the destination port of everything it gets
+    buf: Vec<u8>, // Where each datagram is received before being copied out
+}
+
+impl Incoming {
+    pub fn new(socket: net::UdpSocket, handle: &Handle) -> io::Result<Incoming> {
+        let port = socket.local_addr()?.port();
+        enable(&socket, libc::IP_RECVTTL)?;
+        enable(&socket, libc::IP_PKTINFO)?;
+        let io = PollEvented2::new_with_handle(mio::net::UdpSocket::from_socket(socket)?, handle)?;
+        Ok(Incoming {
+            io,
+            port,
+            buf: vec![0; MAX_PAYLOAD],
+        })
+    }
+}
+
+impl Stream for Incoming {
+    type Item = Datagram;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Datagram>, io::Error> {
+        if let Async::NotReady = self.io.poll_read_ready(Ready::readable())? {
+            return Ok(Async::NotReady);
+        }
+        match recv(self.io.get_ref().as_raw_fd(), &mut self.buf, self.port) {
+            Ok(d) => Ok(Async::Ready(Some(d))),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.io.clear_read_ready(Ready::readable())?;
+                Ok(Async::NotReady)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Turn on an IP level socket option that makes the kernel report a header field
+fn enable(socket: &net::UdpSocket, option: libc::c_int) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            option,
+            &on as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive one datagram into `buf`, reading its TTL and destination out of the control messages
+fn recv(fd: RawFd, buf: &mut [u8], port: u16) -> io::Result<Datagram> {
+    let mut src: libc::sockaddr_in = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // u64s keep the buffer aligned for the cmsghdrs written into it
+    let mut control = [0u64; 16];
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_name = &mut src as *mut libc::sockaddr_in as *mut libc::c_void;
+    hdr.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    hdr.msg_controllen = mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(fd, &mut hdr, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let (mut ttl, mut dst) = (None, None);
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&hdr);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_TTL) => {
+                    ttl = Some(ptr::read_unaligned(data as *const libc::c_int) as u8);
+                }
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    let info = ptr::read_unaligned(data as *const libc::in_pktinfo);
+                    dst = Some(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)));
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&hdr, cmsg);
+        }
+    }
+    let missing = |field| io::Error::new(io::ErrorKind::InvalidData, field);
+    Ok(Datagram {
+        time: Utc::now(),
+        src: SocketAddrV4::new(
+            Ipv4Addr::from(u32::from_be(src.sin_addr.s_addr)),
+            u16::from_be(src.sin_port),
+        ),
+        dst: SocketAddrV4::new(
+            dst.ok_or_else(|| missing("No destination address reported"))?,
+            port,
+        ),
+        ttl: ttl.ok_or_else(|| missing("No IP TTL reported"))?,
+        payload: buf[..len as usize].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod test_socket {
+    use super::*;
+
+    use std::net::SocketAddr;
+
+    use self::futures::Future;
+    use self::tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn test_incoming() {
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        let incoming = Incoming::new(socket, &Handle::default()).unwrap();
+
+        let sender = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.set_ttl(7).unwrap();
+        sender.send_to(&[4, 0], addr).unwrap();
+
+        let (d, _) = runtime
+            .block_on(incoming.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        let d = d.unwrap();
+        assert_eq!(d.ttl, 7);
+        assert_eq!(d.payload, vec![4, 0]);
+        assert_eq!(SocketAddr::V4(d.src), sender.local_addr().unwrap());
+        assert_eq!(SocketAddr::V4(d.dst), addr);
+    }
+}
@@ -0,0 +1,234 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Utc};
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::routing::{RouteEntry, RouteState};
+use super::seq::SeqNum;
+
+/// Version of the state file format written by `SavedState::save`
+pub const STATE_VERSION: u32 = 1;
+
+/// The parts of a node's state that survive a restart of the daemon.
+///
+/// Remembering our own sequence number is what section 6.13 of the RFC is worried about after a
+/// reboot, so a node with recent saved state needn't stay quiet.
+///
+/// The file is line based text:
+///
+/// ```text
+/// aodv-state 1
+/// saved 1508437200000
+/// seq_num 42
+/// rreq_id 7
+/// blacklist 192.168.10.8 1508437205000
+/// route 192.168.10.14 12 1 3 192.168.10.8 wlan0 1508437206000 192.168.10.2,192.168.10.3
+/// ```
+///
+/// Times are milliseconds since the epoch. A route line holds the destination, sequence number,
+/// valid sequence number flag, hop count, next hop, interface, lifetime and precursors (`-` for
+/// none).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedState {
+    pub saved: DateTime<Utc>,                      // When the state was taken
+    pub seq_num: SeqNum,                           // Our own sequence number
+    pub rreq_id: u32,                              // ID of the last RREQ we originated
+    pub blacklist: Vec<(Ipv4Addr, DateTime<Utc>)>, // Blacklisted neighbours and their expiry
+    pub routes: Vec<RouteEntry>,                   // Valid routes
+}
+
+impl SavedState {
+    /// Read the state from `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        contents.parse()
+    }
+    /// Write the state to `path`, replacing the old file only once the new one is complete
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        {
+            let mut f = File::create(&tmp)?;
+            f.write_all(self.to_string().as_bytes())?;
+            f.sync_all()?;
+        }
+        fs::rename(tmp, path)
+    }
+}
+
+impl fmt::Display for SavedState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "aodv-state {}", STATE_VERSION)?;
+        writeln!(f, "saved {}", self.saved.timestamp_millis())?;
+        writeln!(f, "seq_num {}", self.seq_num)?;
+        writeln!(f, "rreq_id {}", self.rreq_id)?;
+        for (ip, expiry) in &self.blacklist {
+            writeln!(f, "blacklist {} {}", ip, expiry.timestamp_millis())?;
+        }
+        for r in &self.routes {
+            let precursors = if r.precursors.is_empty() {
+                String::from("-")
+            } else {
+                let ips: Vec<String> = r.precursors.iter().map(|ip| ip.to_string()).collect();
+                ips.join(",")
+            };
+            writeln!(
+                f,
+                "route {} {} {} {} {} {} {} {}",
+                r.dest_ip,
+                r.dest_seq_num,
+                r.valid_seq_num as u8,
+                r.hop_count,
+                r.next_hop,
+                r.interface,
+                r.lifetime.timestamp_millis(),
+                precursors
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SavedState {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut lines = s.lines().filter(|l| !l.trim().is_empty());
+
+        let header: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+        match header.as_slice() {
+            ["aodv-state", v] if v.parse() == Ok(STATE_VERSION) => {}
+            ["aodv-state", v] => return Err(invalid(&format!("unsupported version {}", v))),
+            _ => return Err(invalid("missing aodv-state header")),
+        }
+
+        let mut saved = None;
+        let mut seq_num = None;
+        let mut rreq_id = None;
+        let mut blacklist = Vec::new();
+        let mut routes = Vec::new();
+
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let bad = || invalid(&format!("bad line: {}", line));
+            match fields.as_slice() {
+                ["saved", t] => saved = Some(time(t).ok_or_else(bad)?),
+                ["seq_num", n] => seq_num = Some(SeqNum(n.parse().map_err(|_| bad())?)),
+                ["rreq_id", n] => rreq_id = Some(n.parse().map_err(|_| bad())?),
+                ["blacklist", ip, t] => {
+                    blacklist.push((ip.parse().map_err(|_| bad())?, time(t).ok_or_else(bad)?))
+                }
+                ["route", dest, seq, valid, hops, next_hop, interface, lifetime, precursors] => {
+                    let precursors = match *precursors {
+                        "-" => Vec::new(),
+                        list => list
+                            .split(',')
+                            .map(|ip| ip.parse().map_err(|_| bad()))
+                            .collect::<io::Result<_>>()?,
+                    };
+                    routes.push(RouteEntry {
+                        dest_ip: dest.parse().map_err(|_| bad())?,
+                        dest_seq_num: SeqNum(seq.parse().map_err(|_| bad())?),
+                        valid_seq_num: *valid == "1",
+                        state: RouteState::Valid,
+                        interface: String::from(*interface),
+                        hop_count: hops.parse().map_err(|_| bad())?,
+                        next_hop: next_hop.parse().map_err(|_| bad())?,
+                        precursors,
+                        lifetime: time(lifetime).ok_or_else(bad)?,
+                    });
+                }
+                _ => return Err(bad()),
+            }
+        }
+
+        match (saved, seq_num, rreq_id) {
+            (Some(saved), Some(seq_num), Some(rreq_id)) => Ok(SavedState {
+                saved,
+                seq_num,
+                rreq_id,
+                blacklist,
+                routes,
+            }),
+            _ => Err(invalid("missing saved, seq_num or rreq_id")),
+        }
+    }
+}
+
+/// Parse milliseconds since the epoch
+fn time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(s.parse().ok()?)
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("state file: {}", reason),
+    )
+}
+
+#[cfg(test)]
+mod test_state {
+    use super::*;
+
+    fn state() -> SavedState {
+        let now = DateTime::from_timestamp_millis(Utc::now().timestamp_millis()).unwrap();
+        SavedState {
+            saved: now,
+            seq_num: SeqNum(42),
+            rreq_id: 7,
+            blacklist: vec![(Ipv4Addr::new(192, 168, 10, 8), now)],
+            routes: vec![RouteEntry {
+                dest_ip: Ipv4Addr::new(192, 168, 10, 14),
+                dest_seq_num: SeqNum(12),
+                valid_seq_num: true,
+                state: RouteState::Valid,
+                interface: String::from("wlan0"),
+                hop_count: 3,
+                next_hop: Ipv4Addr::new(192, 168, 10, 8),
+                precursors: vec![
+                    Ipv4Addr::new(192, 168, 10, 2),
+                    Ipv4Addr::new(192, 168, 10, 3),
+                ],
+                lifetime: now,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let state = state();
+        assert_eq!(state.to_string().parse::<SavedState>().unwrap(), state);
+
+        let mut tmp = std::env::temp_dir();
+        tmp.push("aodv-test.state");
+        state.save(&tmp).unwrap();
+        assert_eq!(SavedState::load(&tmp).unwrap(), state);
+        fs::remove_file(tmp).unwrap();
+    }
+
+    #[test]
+    fn test_bad_state() {
+        let state = state().to_string();
+        assert!(state
+            .replace("aodv-state 1", "aodv-state 2")
+            .parse::<SavedState>()
+            .is_err());
+        assert!(state
+            .replace("seq_num 42", "seq_num x")
+            .parse::<SavedState>()
+            .is_err());
+        assert!(state
+            .replace("rreq_id 7\n", "")
+            .parse::<SavedState>()
+            .is_err());
+        assert!("".parse::<SavedState>().is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use super::error::TextError;
+use super::{AodvMessage, Extension, MessageKind, RERR, RREP, RREQ};
+
+// One line per message: its type followed by `key=value` fields, e.g.
+// `RREQ id=14425 flags=J,G,U hops=144 dest=192.168.10.14/12 orig=192.168.10.19/63`.
+// Addresses that carry a sequence number are written `ip/seq`, flags as the letters of those
+// that are set or `-` for none, and extensions as a comma separated `ext=` field.
+
+impl fmt::Display for AodvMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AodvMessage::Rreq(ref r) => r.fmt(f),
+            AodvMessage::Rrep(ref r) => write_rrep(f, MessageKind::Rrep, r),
+            AodvMessage::Hello(ref r) => write_rrep(f, MessageKind::Hello, r),
+            AodvMessage::Rerr(ref r) => r.fmt(f),
+            AodvMessage::Ack => MessageKind::Ack.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for RREQ {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} id={} flags=", MessageKind::Rreq, self.rreq_id)?;
+        write_flags(
+            f,
+            &[
+                ('J', self.j),
+                ('R', self.r),
+                ('G', self.g),
+                ('D', self.d),
+                ('U', self.u),
+            ],
+        )?;
+        write!(
+            f,
+            " hops={} dest={}/{} orig={}/{}",
+            self.hop_count, self.dest_ip, self.dest_seq_num, self.orig_ip, self.orig_seq_num
+        )?;
+        write_extensions(f, &self.extensions)
+    }
+}
+
+impl fmt::Display for RREP {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_rrep(f, MessageKind::Rrep, self)
+    }
+}
+
+impl fmt::Display for RERR {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} flags=", MessageKind::Rerr)?;
+        write_flags(f, &[('N', self.n)])?;
+        let dests: Vec<String> = self
+            .udests()
+            .iter()
+            .map(|&(ip, seq)| format!("{}/{}", ip, seq))
+            .collect();
+        write!(f, " dests={}", dests.join(","))?;
+        write_extensions(f, &self.extensions)
+    }
+}
+
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Extension::HelloInterval(interval) => write!(f, "hello:{}", interval),
+            Extension::Timestamp(t) => write!(f, "timestamp:{}", t),
+            Extension::Unknown { kind, ref data } => {
+                write!(f, "{}:", kind)?;
+                for b in data {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Write a RREP under `kind`, so that a Hello reads back as one
+fn write_rrep(f: &mut fmt::Formatter, kind: MessageKind, r: &RREP) -> fmt::Result {
+    write!(f, "{} flags=", kind)?;
+    write_flags(f, &[('R', r.r), ('A', r.a)])?;
+    write!(
+        f,
+        " prefix={} hops={} dest={}/{} orig={} lifetime={}",
+        r.prefix_size, r.hop_count, r.dest_ip, r.dest_seq_num, r.orig_ip, r.lifetime
+    )?;
+    write_extensions(f, &r.extensions)
+}
+
+/// Write the letters of the flags that are set, or `-` if none are
+fn write_flags(f: &mut fmt::Formatter, flags: &[(char, bool)]) -> fmt::Result {
+    let set: Vec<String> = flags
+        .iter()
+        .filter(|&&(_, on)| on)
+        .map(|&(c, _)| c.to_string())
+        .collect();
+    if set.is_empty() {
+        f.write_str("-")
+    } else {
+        f.write_str(&set.join(","))
+    }
+}
+
+fn write_extensions(f: &mut fmt::Formatter, extensions: &[Extension]) -> fmt::Result {
+    if extensions.is_empty() {
+        return Ok(());
+    }
+    let exts: Vec<String> = extensions.iter().map(|e| e.to_string()).collect();
+    write!(f, " ext={}", exts.join(","))
+}
+
+impl FromStr for AodvMessage {
+    type Err = TextError;
+
+    fn from_str(s: &str) -> Result<Self, TextError> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (kind, rest) = match words.split_first() {
+            Some((kind, rest)) => (*kind, rest),
+            None => return Err(TextError::UnknownKind(String::new())),
+        };
+        match kind {
+            "RREQ" => Ok(AodvMessage::Rreq(rreq(rest)?)),
+            "RREP" => Ok(AodvMessage::Rrep(rrep(rest)?)),
+            "HELLO" => Ok(AodvMessage::Hello(rrep(rest)?)),
+            "RERR" => Ok(AodvMessage::Rerr(rerr(rest)?)),
+            "RREP-ACK" => match rest.first() {
+                Some(word) => Err(TextError::BadField(word.to_string())),
+                None => Ok(AodvMessage::Ack),
+            },
+            kind => Err(TextError::UnknownKind(kind.to_string())),
+        }
+    }
+}
+
+impl FromStr for RREQ {
+    type Err = TextError;
+
+    fn from_str(s: &str) -> Result<Self, TextError> {
+        match s.parse()? {
+            AodvMessage::Rreq(r) => Ok(r),
+            m => Err(TextError::UnknownKind(m.kind().to_string())),
+        }
+    }
+}
+
+impl FromStr for RREP {
+    type Err = TextError;
+
+    /// Parse a RREP, or a Hello since that is just a RREP of a particular shape
+    fn from_str(s: &str) -> Result<Self, TextError> {
+        match s.parse()? {
+            AodvMessage::Rrep(r) | AodvMessage::Hello(r) => Ok(r),
+            m => Err(TextError::UnknownKind(m.kind().to_string())),
+        }
+    }
+}
+
+impl FromStr for RERR {
+    type Err = TextError;
+
+    fn from_str(s: &str) -> Result<Self, TextError> {
+        match s.parse()? {
+            AodvMessage::Rerr(r) => Ok(r),
+            m => Err(TextError::UnknownKind(m.kind().to_string())),
+        }
+    }
+}
+
+impl FromStr for Extension {
+    type Err = TextError;
+
+    /// Parse `hello:ms`, `timestamp:ms` or `type:hex` as written by `Display`
+    fn from_str(s: &str) -> Result<Self, TextError> {
+        let bad = || TextError::BadField(s.to_string());
+        let (kind, value) = s.split_once(':').ok_or_else(bad)?;
+        match kind {
+            "hello" => Ok(Extension::HelloInterval(value.parse().map_err(|_| bad())?)),
+            "timestamp" => Ok(Extension::Timestamp(value.parse().map_err(|_| bad())?)),
+            kind => {
+                let kind: u8 = kind.parse().map_err(|_| bad())?;
+                if !value.is_ascii() || value.len() % 2 == 1 || value.len() > 2 * 255 {
+                    return Err(bad());
+                }
+                let mut b = vec![kind, (value.len() / 2) as u8];
+                for i in (0..value.len()).step_by(2) {
+                    b.push(u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| bad())?);
+                }
+                // Go through the binary parser so that a numbered known type is decoded too
+                match Extension::parse_all(&b) {
+                    Ok(mut extensions) => Ok(extensions.remove(0)),
+                    Err(_) => Err(bad()),
+                }
+            }
+        }
+    }
+}
+
+fn rreq(words: &[&str]) -> Result<RREQ, TextError> {
+    let fields = Fields::new(words, &["id", "flags", "hops", "dest", "orig", "ext"])?;
+    let flags = fields.flags(&['J', 'R', 'G', 'D', 'U'])?;
+    let (dest_ip, dest_seq_num) = fields.addr_seq("dest")?;
+    let (orig_ip, orig_seq_num) = fields.addr_seq("orig")?;
+    Ok(RREQ {
+        j: flags.contains(&'J'),
+        r: flags.contains(&'R'),
+        g: flags.contains(&'G'),
+        d: flags.contains(&'D'),
+        u: flags.contains(&'U'),
+        hop_count: fields.get("hops")?.unwrap_or(0),
+        rreq_id: fields.get("id")?.unwrap_or(0),
+        dest_ip,
+        dest_seq_num,
+        orig_ip,
+        orig_seq_num,
+        extensions: fields.extensions()?,
+    })
+}
+
+fn rrep(words: &[&str]) -> Result<RREP, TextError> {
+    let fields = Fields::new(
+        words,
+        &["flags", "prefix", "hops", "dest", "orig", "lifetime", "ext"],
+    )?;
+    let flags = fields.flags(&['R', 'A'])?;
+    let prefix_size = fields.get("prefix")?.unwrap_or(0);
+    if prefix_size > 31 {
+        return Err(TextError::BadField(format!("prefix={}", prefix_size)));
+    }
+    let (dest_ip, dest_seq_num) = fields.addr_seq("dest")?;
+    Ok(RREP {
+        r: flags.contains(&'R'),
+        a: flags.contains(&'A'),
+        prefix_size,
+        hop_count: fields.get("hops")?.unwrap_or(0),
+        dest_ip,
+        dest_seq_num,
+        orig_ip: fields.get("orig")?.ok_or(TextError::Missing("orig"))?,
+        lifetime: fields.get("lifetime")?.unwrap_or(0),
+        extensions: fields.extensions()?,
+    })
+}
+
+fn rerr(words: &[&str]) -> Result<RERR, TextError> {
+    let fields = Fields::new(words, &["flags", "dests", "ext"])?;
+    let flags = fields.flags(&['N'])?;
+    let dests = fields.raw("dests").ok_or(TextError::Missing("dests"))?;
+    let udest_list = dests
+        .split(',')
+        .map(|d| addr_seq(d).ok_or_else(|| TextError::BadField(format!("dests={}", dests))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut rerr = RERR::with_dests(flags.contains(&'N'), udest_list)
+        .map_err(|_| TextError::BadField(format!("dests={}", dests)))?;
+    rerr.extensions = fields.extensions()?;
+    Ok(rerr)
+}
+
+/// Parse `ip/seq`
+fn addr_seq(s: &str) -> Option<(Ipv4Addr, u32)> {
+    let (ip, seq) = s.split_once('/')?;
+    Some((ip.parse().ok()?, seq.parse().ok()?))
+}
+
+/// The `key=value` fields following the message type
+struct Fields<'a> {
+    fields: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Fields<'a> {
+    /// Collect the fields, each of which must be one of `known` and appear only once
+    fn new(words: &[&'a str], known: &[&str]) -> Result<Self, TextError> {
+        let mut fields = HashMap::new();
+        for word in words {
+            match word.split_once('=') {
+                Some((key, value)) if known.contains(&key) && !fields.contains_key(key) => {
+                    fields.insert(key, value);
+                }
+                _ => return Err(TextError::BadField(word.to_string())),
+            }
+        }
+        Ok(Fields { fields })
+    }
+    fn raw(&self, key: &str) -> Option<&'a str> {
+        self.fields.get(key).cloned()
+    }
+    fn bad(&self, key: &str) -> TextError {
+        TextError::BadField(format!("{}={}", key, self.fields[key]))
+    }
+    /// Parse the field if it is there
+    fn get<T: FromStr>(&self, key: &str) -> Result<Option<T>, TextError> {
+        match self.raw(key) {
+            Some(value) => value.parse().map(Some).map_err(|_| self.bad(key)),
+            None => Ok(None),
+        }
+    }
+    fn addr_seq(&self, key: &'static str) -> Result<(Ipv4Addr, u32), TextError> {
+        let value = self.raw(key).ok_or(TextError::Missing(key))?;
+        addr_seq(value).ok_or_else(|| self.bad(key))
+    }
+    /// Return the flags that are set, each of which must be one of `known`
+    fn flags(&self, known: &[char]) -> Result<Vec<char>, TextError> {
+        match self.raw("flags") {
+            None | Some("-") => Ok(Vec::new()),
+            Some(value) => value
+                .split(',')
+                .map(|flag| {
+                    let mut chars = flag.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if known.contains(&c) => Ok(c),
+                        _ => Err(self.bad("flags")),
+                    }
+                })
+                .collect(),
+        }
+    }
+    fn extensions(&self) -> Result<Vec<Extension>, TextError> {
+        match self.raw("ext") {
+            Some(value) => value.split(',').map(str::parse).collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_text {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let lines = [
+            "RREQ id=14425 flags=J,G,U hops=144 dest=192.168.10.14/12 orig=192.168.10.19/63",
+            "RREP flags=R prefix=31 hops=98 dest=192.168.10.14/12 orig=192.168.10.19 \
+             lifetime=32603 ext=timestamp:1508437200000,200:0102ff",
+            "HELLO flags=- prefix=0 hops=0 dest=192.168.10.14/12 orig=192.168.10.14 \
+             lifetime=2000 ext=hello:1000",
+            "RERR flags=N dests=192.168.10.18/482755,255.255.255.255/0",
+            "RREP-ACK",
+        ];
+        for line in &lines {
+            let msg: AodvMessage = line.parse().unwrap();
+            assert_eq!(msg.to_string(), *line);
+        }
+
+        let rreq: RREQ = lines[0].parse().unwrap();
+        let bytes: &[u8] = &[
+            1, 168, 0, 144, 0, 0, 56, 89, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 19, 0, 0, 0,
+            63,
+        ];
+        assert_eq!(rreq, RREQ::new(bytes).unwrap());
+        match lines[2].parse().unwrap() {
+            AodvMessage::Hello(r) => assert_eq!(r.hello_interval(), Some(1000)),
+            m => panic!("expected a Hello, got {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        // Written by hand, fields can come in any order and most can be left out
+        let rreq: RREQ = "RREQ orig=10.0.0.1/3 dest=10.0.0.2/0".parse().unwrap();
+        assert_eq!(
+            rreq,
+            RREQ::builder()
+                .dest(Ipv4Addr::new(10, 0, 0, 2))
+                .dest_seq_num(0)
+                .orig(Ipv4Addr::new(10, 0, 0, 1), 3)
+                .build()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_text_errors() {
+        assert_eq!(
+            "RREX id=1".parse::<AodvMessage>(),
+            Err(TextError::UnknownKind(String::from("RREX")))
+        );
+        assert_eq!(
+            "RREQ dest=10.0.0.2/0".parse::<AodvMessage>(),
+            Err(TextError::Missing("orig"))
+        );
+        assert_eq!(
+            "RREQ flags=N dest=10.0.0.2/0 orig=10.0.0.1/3".parse::<AodvMessage>(),
+            Err(TextError::BadField(String::from("flags=N")))
+        );
+        assert_eq!(
+            "RREP prefix=32 dest=10.0.0.2/0 orig=10.0.0.1".parse::<AodvMessage>(),
+            Err(TextError::BadField(String::from("prefix=32")))
+        );
+        assert_eq!(
+            "RERR dests=10.0.0.2/0 dests=10.0.0.3/0".parse::<AodvMessage>(),
+            Err(TextError::BadField(String::from("dests=10.0.0.3/0")))
+        );
+        assert_eq!(
+            "RREP-ACK".parse::<RERR>(),
+            Err(TextError::UnknownKind(String::from("RREP-ACK")))
+        );
+        // A numbered known extension of another length is kept as is
+        assert_eq!(
+            "1:0003e8".parse::<Extension>(),
+            Ok(Extension::Unknown {
+                kind: 1,
+                data: vec![0, 3, 232],
+            })
+        );
+        assert_eq!(
+            "1:000003e8".parse::<Extension>(),
+            Ok(Extension::HelloInterval(1000))
+        );
+    }
+}
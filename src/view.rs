@@ -0,0 +1,279 @@
+use std::net::Ipv4Addr;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::extension::Extension;
+use super::{check_len, ParseError};
+
+/// Return the IPv4 address at the start of `b`
+fn ip(b: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(b[0], b[1], b[2], b[3])
+}
+
+/// A RREQ read straight out of a byte buffer, checked but not copied
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RreqView<B> {
+    b: B,
+}
+
+impl<B: AsRef<[u8]>> RreqView<B> {
+    /// Check that `b` holds a RREQ and wrap it
+    pub fn new(b: B) -> Result<Self, ParseError> {
+        check_len(b.as_ref(), 1, 24)?;
+        Extension::check_all(&b.as_ref()[24..])?;
+        Ok(RreqView { b })
+    }
+    pub fn j(&self) -> bool {
+        self.b.as_ref()[1] & 1 << 7 != 0
+    }
+    pub fn r(&self) -> bool {
+        self.b.as_ref()[1] & 1 << 6 != 0
+    }
+    pub fn g(&self) -> bool {
+        self.b.as_ref()[1] & 1 << 5 != 0
+    }
+    pub fn d(&self) -> bool {
+        self.b.as_ref()[1] & 1 << 4 != 0
+    }
+    pub fn u(&self) -> bool {
+        self.b.as_ref()[1] & 1 << 3 != 0
+    }
+    pub fn hop_count(&self) -> u8 {
+        self.b.as_ref()[3]
+    }
+    pub fn rreq_id(&self) -> u32 {
+        BigEndian::read_u32(&self.b.as_ref()[4..8])
+    }
+    pub fn dest_ip(&self) -> Ipv4Addr {
+        ip(&self.b.as_ref()[8..12])
+    }
+    pub fn dest_seq_num(&self) -> u32 {
+        BigEndian::read_u32(&self.b.as_ref()[12..16])
+    }
+    pub fn orig_ip(&self) -> Ipv4Addr {
+        ip(&self.b.as_ref()[16..20])
+    }
+    pub fn orig_seq_num(&self) -> u32 {
+        BigEndian::read_u32(&self.b.as_ref()[20..24])
+    }
+    /// Return the raw extensions following the fixed part
+    pub fn extension_bytes(&self) -> &[u8] {
+        &self.b.as_ref()[24..]
+    }
+    /// Return the whole message
+    pub fn as_bytes(&self) -> &[u8] {
+        self.b.as_ref()
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> RreqView<B> {
+    pub fn set_hop_count(&mut self, hop_count: u8) {
+        self.b.as_mut()[3] = hop_count;
+    }
+    /// Count one more hop before forwarding, saturating at 255
+    pub fn increment_hop_count(&mut self) {
+        let hop_count = self.hop_count().saturating_add(1);
+        self.set_hop_count(hop_count);
+    }
+}
+
+/// A RREP read straight out of a byte buffer, checked but not copied
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RrepView<B> {
+    b: B,
+}
+
+impl<B: AsRef<[u8]>> RrepView<B> {
+    /// Check that `b` holds a RREP and wrap it
+    pub fn new(b: B) -> Result<Self, ParseError> {
+        check_len(b.as_ref(), 2, 20)?;
+        Extension::check_all(&b.as_ref()[20..])?;
+        Ok(RrepView { b })
+    }
+    pub fn r(&self) -> bool {
+        self.b.as_ref()[1] & 1 << 7 != 0
+    }
+    pub fn a(&self) -> bool {
+        self.b.as_ref()[1] & 1 << 6 != 0
+    }
+    pub fn prefix_size(&self) -> u8 {
+        self.b.as_ref()[2] % 32
+    }
+    pub fn hop_count(&self) -> u8 {
+        self.b.as_ref()[3]
+    }
+    pub fn dest_ip(&self) -> Ipv4Addr {
+        ip(&self.b.as_ref()[4..8])
+    }
+    pub fn dest_seq_num(&self) -> u32 {
+        BigEndian::read_u32(&self.b.as_ref()[8..12])
+    }
+    pub fn orig_ip(&self) -> Ipv4Addr {
+        ip(&self.b.as_ref()[12..16])
+    }
+    pub fn lifetime(&self) -> u32 {
+        BigEndian::read_u32(&self.b.as_ref()[16..20])
+    }
+    /// Return whether this RREP has the shape of a Hello message as per section 6.9 of the RFC
+    pub fn is_hello(&self) -> bool {
+        self.dest_ip() == self.orig_ip() && self.hop_count() == 0
+    }
+    /// Return the raw extensions following the fixed part
+    pub fn extension_bytes(&self) -> &[u8] {
+        &self.b.as_ref()[20..]
+    }
+    /// Return the whole message
+    pub fn as_bytes(&self) -> &[u8] {
+        self.b.as_ref()
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> RrepView<B> {
+    pub fn set_hop_count(&mut self, hop_count: u8) {
+        self.b.as_mut()[3] = hop_count;
+    }
+    /// Count one more hop before forwarding, saturating at 255
+    pub fn increment_hop_count(&mut self) {
+        let hop_count = self.hop_count().saturating_add(1);
+        self.set_hop_count(hop_count);
+    }
+}
+
+/// A RERR read straight out of a byte buffer, checked but not copied
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RerrView<B> {
+    b: B,
+}
+
+impl<B: AsRef<[u8]>> RerrView<B> {
+    /// Check that `b` holds a RERR with as many destinations as it says and wrap it
+    pub fn new(b: B) -> Result<Self, ParseError> {
+        {
+            let b = b.as_ref();
+            if b.len() < 12 {
+                return Err(ParseError::Truncated {
+                    expected: 12,
+                    got: b.len(),
+                });
+            }
+            if b[0] != 3 {
+                return Err(ParseError::WrongType {
+                    expected: 3,
+                    got: b[0],
+                });
+            }
+            // Destinations that don't line up with the count look like broken extensions
+            let count = b[3];
+            let end = 4 + 8 * count as usize;
+            let mismatch = ParseError::DestCountMismatch {
+                count,
+                found: (b.len() - 4) / 8,
+            };
+            if count == 0 || b.len() < end {
+                return Err(mismatch);
+            }
+            match Extension::check_all(&b[end..]) {
+                Ok(()) => {}
+                Err(_) if b.len() % 8 == 4 => return Err(mismatch),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(RerrView { b })
+    }
+    pub fn n(&self) -> bool {
+        self.b.as_ref()[1] & 1 << 7 != 0
+    }
+    pub fn dest_count(&self) -> u8 {
+        self.b.as_ref()[3]
+    }
+    /// Iterate over the unreachable destinations and their sequence numbers
+    pub fn udests(&self) -> impl Iterator<Item = (Ipv4Addr, u32)> + '_ {
+        self.b.as_ref()[4..self.extensions_start()]
+            .chunks(8)
+            .map(|c| (ip(c), BigEndian::read_u32(&c[4..8])))
+    }
+    /// Return the raw extensions following the unreachable destinations
+    pub fn extension_bytes(&self) -> &[u8] {
+        &self.b.as_ref()[self.extensions_start()..]
+    }
+    /// Return the whole message
+    pub fn as_bytes(&self) -> &[u8] {
+        self.b.as_ref()
+    }
+    fn extensions_start(&self) -> usize {
+        4 + 8 * self.dest_count() as usize
+    }
+}
+
+#[cfg(test)]
+mod test_view {
+    use super::*;
+
+    #[test]
+    fn test_rreq_view() {
+        let mut b = [
+            1, 168, 0, 144, 0, 0, 56, 89, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 19, 0, 0, 0,
+            63,
+        ];
+        {
+            let view = RreqView::new(&b[..]).unwrap();
+            assert!(view.j() && !view.r() && view.g() && !view.d() && view.u());
+            assert_eq!((view.rreq_id(), view.hop_count()), (14425, 144));
+            assert_eq!(view.dest_ip(), Ipv4Addr::new(192, 168, 10, 14));
+            assert_eq!((view.dest_seq_num(), view.orig_seq_num()), (12, 63));
+            assert!(view.extension_bytes().is_empty());
+        }
+
+        // Patched where it lies
+        let mut view = RreqView::new(&mut b[..]).unwrap();
+        view.increment_hop_count();
+        assert_eq!(b[3], 145);
+
+        assert_eq!(
+            RreqView::new(&b[..20]),
+            Err(ParseError::Truncated {
+                expected: 24,
+                got: 20
+            })
+        );
+    }
+
+    #[test]
+    fn test_rrep_view() {
+        let mut b = vec![
+            2, 128, 31, 98, 192, 168, 10, 14, 0, 0, 0, 12, 192, 168, 10, 19, 0, 0, 127, 91,
+        ];
+        b.extend_from_slice(&[200, 1, 7]);
+        let view = RrepView::new(&b).unwrap();
+        assert!(view.r() && !view.a() && !view.is_hello());
+        assert_eq!((view.prefix_size(), view.hop_count()), (31, 98));
+        assert_eq!((view.dest_seq_num(), view.lifetime()), (12, 32603));
+        assert_eq!(view.orig_ip(), Ipv4Addr::new(192, 168, 10, 19));
+        assert_eq!(view.extension_bytes(), &[200, 1, 7]);
+
+        b[3] = 255;
+        let mut view = RrepView::new(&mut b).unwrap();
+        view.increment_hop_count();
+        assert_eq!(view.hop_count(), 255);
+    }
+
+    #[test]
+    fn test_rerr_view() {
+        let b: &[u8] = &[
+            3, 128, 0, 2, 192, 168, 10, 18, 0, 7, 93, 195, 255, 255, 255, 255, 0, 0, 0, 0,
+        ];
+        let view = RerrView::new(b).unwrap();
+        assert!(view.n());
+        assert_eq!(
+            view.udests().collect::<Vec<_>>(),
+            vec![
+                (Ipv4Addr::new(192, 168, 10, 18), 482755),
+                (Ipv4Addr::new(255, 255, 255, 255), 0)
+            ]
+        );
+        assert_eq!(
+            RerrView::new(&b[..12]),
+            Err(ParseError::DestCountMismatch { count: 2, found: 1 })
+        );
+    }
+}